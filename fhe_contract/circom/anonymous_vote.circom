@@ -0,0 +1,65 @@
+pragma circom 2.0.0;
+
+include "./circomlib/circuits/poseidon.circom";
+
+// a vote from a member of the allowlist that doesn't say which one, see anonymous::AnonymousGroup
+template anonymous_vote(depth) {
+
+   // this is our vote
+   signal input vote[10];
+   // hash of the encrypted vote and of the key it is encrypted to, see groth16::BallotBinding
+   signal input binding;
+   // hash of the contract ID, see groth16::AnonymousStatement
+   signal input contract;
+
+   // only known to the voter, see anonymous::VoterIdentity
+   signal input identity_secret;
+   // siblings of our commitment in the tree of the allowlist, and whether we are on the right
+   signal input path_elements[depth];
+   signal input path_indices[depth];
+
+   // root of the tree we are part of
+   signal output root;
+   // the same for all our votes on this contract, so only one of them is counted
+   signal output nullifier;
+
+   // a public input in no constraint could be changed without invalidating the proof
+   signal binding_square;
+   binding_square <== binding * binding;
+
+   // every option is 0 or 1, and exactly one of them is 1
+   var sum = 0;
+   for(var i = 0; i < 10; i++){
+      vote[i] * (vote[i] - 1) === 0;
+      sum += vote[i];
+   }
+   sum === 1;
+
+   // our leaf is poseidon(secret)
+   component commitment = Poseidon(1);
+   commitment.inputs[0] <== identity_secret;
+
+   component hashers[depth];
+   signal left[depth];
+   signal nodes[depth + 1];
+   nodes[0] <== commitment.out;
+   for(var i = 0; i < depth; i++){
+      path_indices[i] * (1 - path_indices[i]) === 0;
+
+      // on the right, the sibling goes first
+      left[i] <== nodes[i] + path_indices[i] * (path_elements[i] - nodes[i]);
+      hashers[i] = Poseidon(2);
+      hashers[i].inputs[0] <== left[i];
+      hashers[i].inputs[1] <== nodes[i] + path_elements[i] - left[i];
+      nodes[i + 1] <== hashers[i].out;
+   }
+   root <== nodes[depth];
+
+   component nullifier_hash = Poseidon(2);
+   nullifier_hash.inputs[0] <== identity_secret;
+   nullifier_hash.inputs[1] <== contract;
+   nullifier <== nullifier_hash.out;
+}
+
+// the depth is anonymous::ANONYMOUS_TREE_DEPTH
+component main {public [binding, contract]} = anonymous_vote(10);
@@ -1 +1 @@
-snarkjs powersoftau prepare phase2 pot12_0001.ptau pot12_final.ptau -v && snarkjs groth16 setup ./circom/vote_is_valid.r1cs pot12_final.ptau vote_is_valid_0000.zkey && snarkjs zkey contribute vote_is_valid_0000.zkey vote_is_valid_0001.zkey --name="1st Contributor Name" -v && snarkjs zkey export verificationkey vote_is_valid_0001.zkey verification_key.json
+cd "$(dirname "$0")" && snarkjs powersoftau prepare phase2 pot12_0001.ptau pot12_final.ptau -v && snarkjs groth16 setup ./vote_is_valid.r1cs pot12_final.ptau vote_is_valid_0000.zkey && snarkjs zkey contribute vote_is_valid_0000.zkey vote_is_valid_0001.zkey --name="1st Contributor Name" -v && snarkjs zkey export verificationkey vote_is_valid_0001.zkey verification_key.json && snarkjs groth16 setup ./anonymous_vote.r1cs pot12_final.ptau anonymous_vote_0000.zkey && snarkjs zkey contribute anonymous_vote_0000.zkey anonymous_vote_0001.zkey --name="1st Contributor Name" -v && snarkjs zkey export verificationkey anonymous_vote_0001.zkey anonymous_verification_key.json
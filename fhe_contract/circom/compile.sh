@@ -1 +1 @@
-ls && circom ./vote_is_valid.circom --r1cs --c --inspect
\ No newline at end of file
+cd "$(dirname "$0")" && circom ./vote_is_valid.circom --r1cs --wasm --c --inspect && circom ./anonymous_vote.circom --r1cs --wasm --inspect
@@ -1 +1 @@
-snarkjs groth16 prove vote_is_valid_0001.zkey witness.wtns proof.json public.json
\ No newline at end of file
+cd "$(dirname "$0")" && snarkjs groth16 prove vote_is_valid_0001.zkey witness.wtns proof.json public.json
@@ -1 +1 @@
-snarkjs zkey export solidityverifier vote_is_valid_0001.zkey verifier.sol
+cd "$(dirname "$0")" && snarkjs zkey export solidityverifier vote_is_valid_0001.zkey verifier.sol
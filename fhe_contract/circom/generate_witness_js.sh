@@ -1 +1 @@
-node ./vote_is_valid_js/generate_witness.js ./vote_is_valid_js/vote_is_valid.wasm ./input.json witness.wtns
+cd "$(dirname "$0")" && node ./vote_is_valid_js/generate_witness.js ./vote_is_valid_js/vote_is_valid.wasm ./input.json witness.wtns
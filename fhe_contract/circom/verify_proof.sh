@@ -1 +1 @@
-snarkjs groth16 verify ./verification_key.json ./public.json ./proof.json
\ No newline at end of file
+cd "$(dirname "$0")" && snarkjs groth16 verify ./verification_key.json ./public.json ./proof.json
@@ -6,8 +6,21 @@ template vote_is_valid() {
 
    // this is our vote
    signal input vote[10];  
+   // hash of the encrypted vote and of the key it is encrypted to, see groth16::BallotBinding
+   signal input binding;
+   // hashes of the contract ID and of the voter's Eth address, see groth16::VoteStatement
+   signal input contract;
+   signal input voter;
    signal output c;  
 
+   // a public input in no constraint could be changed without invalidating the proof
+   signal binding_square;
+   binding_square <== binding * binding;
+   signal contract_square;
+   contract_square <== contract * contract;
+   signal voter_square;
+   voter_square <== voter * voter;
+
    var sum = 0;
    for(var i = 0; i < 10; i++){
       sum += vote[i];      
@@ -53,4 +66,4 @@ template vote_is_valid() {
 }
 
 // for more circuits - https://github.com/iden3/circomlib
-component main= vote_is_valid();
\ No newline at end of file
+component main {public [binding, contract, voter]} = vote_is_valid();
\ No newline at end of file
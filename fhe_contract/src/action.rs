@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What a transaction does to its contract, the `action` of its `Input` tag
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Deploy,
+    ZkSnark,
+    PublicKey,
+    InitState,
+    Vote,
+    AnonymousVote,
+    AggregateVote,
+    Allowlist,
+    Close,
+    Decrypt,
+    /// Anything else, including an `Input` tag that can't be read
+    #[serde(other)]
+    Unknown,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Action::Deploy => "deploy",
+            Action::ZkSnark => "zk_snark",
+            Action::PublicKey => "public_key",
+            Action::InitState => "init_state",
+            Action::Vote => "vote",
+            Action::AnonymousVote => "anonymous_vote",
+            Action::AggregateVote => "aggregate_vote",
+            Action::Allowlist => "allowlist",
+            Action::Close => "close",
+            Action::Decrypt => "decrypt",
+            Action::Unknown => "unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The `Input` tag of a transaction, e.g.
+/// `{"action":"vote","arguments":[],"validity_proof":"<zk params tx id>"}`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Input {
+    pub action: Action,
+    /// Reserved for actions taking more than the data of their transaction, none does yet
+    #[serde(default)]
+    pub arguments: Vec<String>,
+    /// Transaction holding the zk params the proof in the data was made with, for votes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validity_proof: Option<String>,
+}
+
+impl Input {
+    pub fn new(action: Action) -> Self {
+        Input {
+            action,
+            arguments: vec![],
+            validity_proof: None,
+        }
+    }
+
+    pub fn proven_by(mut self, validity_proof: Option<String>) -> Self {
+        self.validity_proof = validity_proof;
+        self
+    }
+
+    /// Reads an `Input` tag, its action is `Unknown` if it isn't one
+    pub fn parse(tag: &str) -> Self {
+        serde_json::from_str(tag).unwrap_or_else(|_| Input::new(Action::Unknown))
+    }
+
+    pub fn tag(&self) -> String {
+        serde_json::to_string(self).expect("an input is always serializable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reads_back_the_input_tag() {
+        let input = Input::new(Action::AnonymousVote).proven_by(Some("zk".to_string()));
+        assert_eq!(
+            input.tag(),
+            r#"{"action":"anonymous_vote","arguments":[],"validity_proof":"zk"}"#
+        );
+        assert_eq!(Input::parse(&input.tag()), input);
+        assert_eq!(
+            serde_json::to_value(Action::ZkSnark).unwrap(),
+            Action::ZkSnark.to_string()
+        );
+
+        // the tags published before the input was valid JSON, and actions we don't know
+        assert_eq!(
+            Input::parse(r#"{"action":"vote", arguments: []}"#).action,
+            Action::Unknown
+        );
+        assert_eq!(
+            Input::parse(r#"{"action":"other"}"#),
+            Input::new(Action::Unknown)
+        );
+    }
+}
@@ -0,0 +1,324 @@
+use crate::anonymous::{field_to_string, parse_field, AnonymousGroup};
+use crate::arweave::{decode_hex, encode_hex, sha_256};
+use crate::error::HarpocratesError;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// Who may vote on a contract, declared in its source at deploy time
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Allowlist {
+    /// Lowercased Eth addresses, published as is
+    Addresses(BTreeSet<String>),
+    /// Hex root of a Merkle tree over the addresses, voters send a `MerkleProof` along with their vote
+    MerkleRoot(String),
+    /// Identity commitments of the voters, who prove they are one of them without saying which,
+    /// see `anonymous::AnonymousGroup`
+    Anonymous(Vec<String>),
+}
+
+/// Members the deployer added or removed after deploying, published as an interaction
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct AllowlistUpdate {
+    pub add: Vec<String>,
+    pub remove: Vec<String>,
+}
+
+/// Path from a member to the root of the tree, `index` being its position among the sorted addresses
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MerkleProof {
+    pub index: usize,
+    /// Hex hashes of the siblings, from the leaf up
+    pub siblings: Vec<String>,
+}
+
+/// Reads a file with one Eth address per line, blank lines and `#` comments are skipped
+pub fn read_addresses(path: &Path) -> Result<BTreeSet<String>, HarpocratesError> {
+    read_lines(path)?
+        .iter()
+        .map(|line| normalize(line))
+        .collect()
+}
+
+/// Reads a file with one identity commitment per line, for an anonymous allowlist
+pub fn read_commitments(path: &Path) -> Result<Vec<String>, HarpocratesError> {
+    read_lines(path)?
+        .iter()
+        .map(|line| parse_field(line).map(|commitment| field_to_string(&commitment)))
+        .collect()
+}
+
+fn read_lines(path: &Path) -> Result<Vec<String>, HarpocratesError> {
+    let content = fs::read_to_string(path).map_err(HarpocratesError::cache(path))?;
+    Ok(content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// Lowercases an Eth address, so checksummed and plain addresses are the same member
+pub fn normalize(address: &str) -> Result<String, HarpocratesError> {
+    let hex = address.strip_prefix("0x").unwrap_or_default();
+    if hex.len() != 40 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(HarpocratesError::Contract(format!(
+            "{:?} is not an Eth address",
+            address
+        )));
+    }
+    Ok(address.to_lowercase())
+}
+
+// leaves and nodes are hashed with a different prefix, so a node can't pass for a member
+fn leaf(address: &str) -> [u8; 32] {
+    sha_256(&[&[0], address.to_lowercase().as_bytes()].concat())
+}
+
+fn node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    sha_256(&[&[1], &left[..], &right[..]].concat())
+}
+
+// every level of the tree, from the leaves up to the root
+fn levels(addresses: &BTreeSet<String>) -> Vec<Vec<[u8; 32]>> {
+    let mut level: Vec<[u8; 32]> = addresses.iter().map(|a| leaf(a)).collect();
+    let mut levels = vec![];
+    while level.len() > 1 {
+        // the last node is paired with itself when there is an odd number of them
+        let next = level
+            .chunks(2)
+            .map(|pair| node(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+            .collect();
+        levels.push(level);
+        level = next;
+    }
+    levels.push(level);
+    levels
+}
+
+/// Hex root of the tree over the addresses
+pub fn merkle_root(addresses: &BTreeSet<String>) -> Result<String, HarpocratesError> {
+    match levels(addresses).last().and_then(|level| level.first()) {
+        Some(root) => Ok(encode_hex(root)),
+        None => Err(HarpocratesError::Contract(
+            "an allowlist needs at least one address".to_string(),
+        )),
+    }
+}
+
+/// Proof that `address` is one of `addresses`, `None` if it isn't
+pub fn merkle_proof(addresses: &BTreeSet<String>, address: &str) -> Option<MerkleProof> {
+    let address = address.to_lowercase();
+    let index = addresses.iter().position(|a| *a == address)?;
+
+    let levels = levels(addresses);
+    let siblings = levels[..levels.len() - 1]
+        .iter()
+        .enumerate()
+        .map(|(depth, level)| {
+            let position = index >> depth;
+            let sibling = level.get(position ^ 1).unwrap_or(&level[position]);
+            encode_hex(sibling)
+        })
+        .collect();
+
+    Some(MerkleProof { index, siblings })
+}
+
+impl MerkleProof {
+    /// Root of the tree `address` belongs to if this proof is right
+    pub fn root(&self, address: &str) -> Result<String, HarpocratesError> {
+        let mut hash = leaf(address);
+        for (depth, sibling) in self.siblings.iter().enumerate() {
+            let sibling: [u8; 32] = decode_hex(sibling)?.try_into().map_err(|_| {
+                HarpocratesError::Serialization(format!("invalid Merkle sibling {}", sibling))
+            })?;
+            hash = match (self.index >> depth) & 1 {
+                0 => node(&hash, &sibling),
+                _ => node(&sibling, &hash),
+            };
+        }
+        Ok(encode_hex(&hash))
+    }
+}
+
+/// The members of a contract while replaying, updates apply to the votes published after them
+pub struct Members {
+    list: Allowlist,
+    // in the order they were added, which is their order in the anonymous tree
+    added: Vec<String>,
+    removed: HashSet<String>,
+    // tree of an anonymous allowlist, built again after every update
+    group: Option<AnonymousGroup>,
+}
+
+impl Members {
+    pub fn new(list: Allowlist) -> Self {
+        Members {
+            list,
+            added: vec![],
+            removed: HashSet::new(),
+            group: None,
+        }
+    }
+
+    pub fn apply(&mut self, update: &AllowlistUpdate) {
+        for address in &update.add {
+            let address = address.to_lowercase();
+            self.removed.remove(&address);
+            if !self.added.contains(&address) {
+                self.added.push(address);
+            }
+        }
+        for address in &update.remove {
+            let address = address.to_lowercase();
+            self.added.retain(|added| *added != address);
+            self.removed.insert(address);
+        }
+        self.group = None;
+    }
+
+    /// The group anonymous voters prove they are part of
+    pub fn group(&mut self) -> Result<&AnonymousGroup, HarpocratesError> {
+        let commitments = match &self.list {
+            Allowlist::Anonymous(commitments) => commitments,
+            _ => {
+                return Err(HarpocratesError::Contract(
+                    "the contract doesn't take anonymous votes".to_string(),
+                ))
+            }
+        };
+
+        let group = match self.group.take() {
+            Some(group) => group,
+            None => {
+                let members = commitments
+                    .iter()
+                    .chain(&self.added)
+                    .filter(|commitment| !self.removed.contains(*commitment))
+                    .map(|commitment| parse_field(commitment))
+                    .collect::<Result<Vec<_>, _>>()?;
+                AnonymousGroup::new(&members)?
+            }
+        };
+        Ok(self.group.insert(group))
+    }
+
+    /// Checks that `address` may vote, `proof` is only needed with a Merkle root
+    pub fn check(
+        &self,
+        address: &str,
+        proof: Option<&MerkleProof>,
+    ) -> Result<(), HarpocratesError> {
+        let address = address.to_lowercase();
+        let not_listed =
+            || HarpocratesError::Contract(format!("{} is not on the allowlist", address));
+
+        if self.removed.contains(&address) {
+            return Err(not_listed());
+        }
+        if self.added.contains(&address) {
+            return Ok(());
+        }
+        match &self.list {
+            Allowlist::Anonymous(_) => Err(HarpocratesError::Contract(
+                "the contract only takes anonymous votes".to_string(),
+            )),
+            Allowlist::Addresses(addresses) if addresses.contains(&address) => Ok(()),
+            Allowlist::Addresses(_) => Err(not_listed()),
+            Allowlist::MerkleRoot(root) => {
+                let proof = proof.ok_or_else(|| {
+                    HarpocratesError::Contract(format!(
+                        "{} sent no proof it is on the allowlist",
+                        address
+                    ))
+                })?;
+                match proof.root(&address)?.eq_ignore_ascii_case(root) {
+                    true => Ok(()),
+                    false => Err(not_listed()),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::anonymous::VoterIdentity;
+
+    fn addresses(count: usize) -> BTreeSet<String> {
+        (0..count).map(|i| format!("0x{:040x}", i)).collect()
+    }
+
+    #[test]
+    fn it_proves_membership() -> Result<(), HarpocratesError> {
+        // odd and even number of leaves, and a single one
+        for count in [1, 2, 5, 8] {
+            let addresses = addresses(count);
+            let root = merkle_root(&addresses)?;
+            let members = Members::new(Allowlist::MerkleRoot(root.clone()));
+
+            for address in &addresses {
+                let proof = merkle_proof(&addresses, address).unwrap();
+                assert_eq!(proof.root(address)?, root);
+                members.check(&address.to_uppercase().replace("0X", "0x"), Some(&proof))?;
+
+                // someone else's proof is of no use
+                let outsider = format!("0x{:040x}", 99);
+                assert!(members.check(&outsider, Some(&proof)).is_err());
+            }
+            assert!(members.check(&format!("0x{:040x}", 0), None).is_err());
+        }
+        assert!(merkle_proof(&addresses(3), "0xnobody").is_none());
+        assert!(merkle_root(&BTreeSet::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn it_applies_updates() -> Result<(), HarpocratesError> {
+        let alice = format!("0x{:040x}", 0xa);
+        let bob = format!("0x{:040x}", 0xb);
+        let mut members = Members::new(Allowlist::Addresses(BTreeSet::from([alice.clone()])));
+
+        members.check(&alice, None)?;
+        assert!(members.check(&bob, None).is_err());
+
+        members.apply(&AllowlistUpdate {
+            add: vec![bob.to_uppercase().replace("0X", "0x")],
+            remove: vec![alice.clone()],
+        });
+        members.check(&bob, None)?;
+        assert!(members.check(&alice, None).is_err());
+
+        assert!(normalize("0x123").is_err());
+        assert!(members.group().is_err());
+        assert_eq!(normalize(&alice.to_uppercase().replace("0X", "0x"))?, alice);
+        Ok(())
+    }
+
+    #[test]
+    fn it_rebuilds_the_anonymous_group() -> Result<(), HarpocratesError> {
+        let voters: Vec<String> = (0..3)
+            .map(|_| field_to_string(&VoterIdentity::random().commitment()))
+            .collect();
+        let mut members = Members::new(Allowlist::Anonymous(voters[..2].to_vec()));
+        assert!(members.check(&format!("0x{:040x}", 0xa), None).is_err());
+
+        let first = members.group()?.root();
+        members.apply(&AllowlistUpdate {
+            add: vec![voters[2].clone()],
+            remove: vec![voters[0].clone()],
+        });
+
+        let expected = [parse_field(&voters[1])?, parse_field(&voters[2])?];
+        assert_eq!(
+            members.group()?.root(),
+            AnonymousGroup::new(&expected)?.root()
+        );
+        assert_ne!(members.group()?.root(), first);
+        Ok(())
+    }
+}
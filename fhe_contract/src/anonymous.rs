@@ -0,0 +1,200 @@
+use crate::error::HarpocratesError;
+use crate::groth16::hash_to_field;
+use ark_bn254::Fr;
+use ark_ff::{PrimeField, UniformRand};
+use ark_std::rand::thread_rng;
+use light_poseidon::{Poseidon, PoseidonHasher};
+use std::str::FromStr;
+
+/// Depth of the Merkle tree in the anonymous_vote circuit, a group can't have more than 2^depth members
+pub const ANONYMOUS_TREE_DEPTH: usize = 10;
+
+/// Poseidon hash of 1 or 2 field elements, the same as circomlib's
+pub fn poseidon(inputs: &[Fr]) -> Fr {
+    Poseidon::<Fr>::new_circom(inputs.len())
+        .and_then(|mut hasher| hasher.hash(inputs))
+        .expect("poseidon takes 1 to 12 inputs")
+}
+
+/// Parses a field element written in decimal, as in snarkjs' JSON files
+pub fn parse_field(value: &str) -> Result<Fr, HarpocratesError> {
+    Fr::from_str(value)
+        .map_err(|_| HarpocratesError::Serialization(format!("invalid field element {}", value)))
+}
+
+pub fn field_to_string(value: &Fr) -> String {
+    value.into_bigint().to_string()
+}
+
+/// Secret of an anonymous voter, only its commitment is put on the allowlist
+#[derive(Clone)]
+pub struct VoterIdentity {
+    pub(crate) secret: Fr,
+}
+
+impl VoterIdentity {
+    pub fn random() -> Self {
+        VoterIdentity {
+            secret: Fr::rand(&mut thread_rng()),
+        }
+    }
+
+    pub fn from_secret(secret: &str) -> Result<Self, HarpocratesError> {
+        Ok(VoterIdentity {
+            secret: parse_field(secret)?,
+        })
+    }
+
+    pub fn secret(&self) -> String {
+        field_to_string(&self.secret)
+    }
+
+    /// `poseidon(secret)`, the leaf of the voter in the tree of the group
+    pub fn commitment(&self) -> Fr {
+        poseidon(&[self.secret])
+    }
+
+    /// `poseidon(secret, contract)`: the same for every vote of this identity on a contract,
+    /// and unrelated from one contract to another
+    pub fn nullifier(&self, contract_id: &str) -> Fr {
+        poseidon(&[self.secret, hash_to_field(contract_id.as_bytes())])
+    }
+}
+
+/// Siblings of a leaf from the bottom of the tree up, and whether the leaf's branch is
+/// the left (0) or the right (1) child at every level
+#[derive(Clone, Debug, PartialEq)]
+pub struct MerklePath {
+    pub elements: Vec<Fr>,
+    pub indices: Vec<u8>,
+}
+
+/// Voters of an anonymous allowlist: a Poseidon Merkle tree over their commitments, in the
+/// order they were listed, and padded with 0 leaves up to `ANONYMOUS_TREE_DEPTH`
+pub struct AnonymousGroup {
+    // the nodes that aren't roots of empty subtrees, level by level, from the leaves up
+    levels: Vec<Vec<Fr>>,
+    // root of an empty subtree at every level
+    zeros: Vec<Fr>,
+}
+
+impl AnonymousGroup {
+    pub fn new(commitments: &[Fr]) -> Result<Self, HarpocratesError> {
+        if commitments.len() > 1 << ANONYMOUS_TREE_DEPTH {
+            return Err(HarpocratesError::Contract(format!(
+                "an anonymous allowlist can't have more than {} members, got {}",
+                1 << ANONYMOUS_TREE_DEPTH,
+                commitments.len()
+            )));
+        }
+
+        let mut hasher = Poseidon::<Fr>::new_circom(2).expect("poseidon takes 2 inputs");
+        let mut hash = |left: Fr, right: Fr| hasher.hash(&[left, right]).expect("2 inputs");
+
+        let mut zeros = vec![Fr::from(0u64)];
+        let mut levels = vec![commitments.to_vec()];
+        for depth in 0..ANONYMOUS_TREE_DEPTH {
+            let zero = zeros[depth];
+            let next = levels[depth]
+                .chunks(2)
+                .map(|pair| hash(pair[0], *pair.get(1).unwrap_or(&zero)))
+                .collect();
+            levels.push(next);
+            zeros.push(hash(zero, zero));
+        }
+
+        Ok(AnonymousGroup { levels, zeros })
+    }
+
+    pub fn root(&self) -> Fr {
+        self.levels[ANONYMOUS_TREE_DEPTH]
+            .first()
+            .copied()
+            .unwrap_or(self.zeros[ANONYMOUS_TREE_DEPTH])
+    }
+
+    /// Path of the first leaf with this commitment, `None` if it isn't a member
+    pub fn path(&self, commitment: &Fr) -> Option<MerklePath> {
+        let index = self.levels[0].iter().position(|leaf| leaf == commitment)?;
+
+        let (elements, indices) = (0..ANONYMOUS_TREE_DEPTH)
+            .map(|depth| {
+                let position = index >> depth;
+                let sibling = self.levels[depth]
+                    .get(position ^ 1)
+                    .copied()
+                    .unwrap_or(self.zeros[depth]);
+                (sibling, (position & 1) as u8)
+            })
+            .unzip();
+
+        Some(MerklePath { elements, indices })
+    }
+}
+
+impl MerklePath {
+    /// Root of the tree `commitment` belongs to if this path is right, as computed by the circuit
+    pub fn root(&self, commitment: &Fr) -> Fr {
+        self.elements
+            .iter()
+            .zip(&self.indices)
+            .fold(*commitment, |node, (sibling, index)| match index {
+                0 => poseidon(&[node, *sibling]),
+                _ => poseidon(&[*sibling, node]),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_hashes_like_circomlib() -> Result<(), HarpocratesError> {
+        // poseidon([1, 2]) from circomlibjs
+        assert_eq!(
+            field_to_string(&poseidon(&[Fr::from(1u64), Fr::from(2u64)])),
+            "7853200120776062878684798364095072458815029376092732009249414926327459813530"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_proves_membership_of_a_group() -> Result<(), HarpocratesError> {
+        let voters: Vec<VoterIdentity> = (0..5).map(|_| VoterIdentity::random()).collect();
+        let commitments: Vec<Fr> = voters.iter().map(|v| v.commitment()).collect();
+        let group = AnonymousGroup::new(&commitments)?;
+
+        for voter in &voters {
+            let path = group.path(&voter.commitment()).unwrap();
+            assert_eq!(path.elements.len(), ANONYMOUS_TREE_DEPTH);
+            assert_eq!(path.root(&voter.commitment()), group.root());
+        }
+        assert!(group.path(&VoterIdentity::random().commitment()).is_none());
+
+        // the root depends on every member
+        let fewer = AnonymousGroup::new(&commitments[1..])?;
+        assert_ne!(fewer.root(), group.root());
+        assert!(
+            AnonymousGroup::new(&vec![Fr::from(1u64); (1 << ANONYMOUS_TREE_DEPTH) + 1]).is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_derives_one_nullifier_per_contract() -> Result<(), HarpocratesError> {
+        let voter = VoterIdentity::random();
+        let same = VoterIdentity::from_secret(&voter.secret())?;
+
+        assert_eq!(voter.nullifier("contract"), same.nullifier("contract"));
+        assert_ne!(
+            voter.nullifier("contract"),
+            voter.nullifier("another contract")
+        );
+        assert_ne!(
+            voter.nullifier("contract"),
+            VoterIdentity::random().nullifier("contract")
+        );
+        Ok(())
+    }
+}
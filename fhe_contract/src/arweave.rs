@@ -1,280 +1,233 @@
 use arloader::{
-    error::Error,
     status::StatusCode,
     transaction::{Base64, FromUtf8Strs, Tag},
     Arweave,
 };
 // use futures::{stream, StreamExt};
-use crate::ethereum::EthClient;
+use crate::action::Input;
+use crate::config::Config;
+use crate::error::HarpocratesError;
+use crate::ethereum::EthSigner;
+use crate::keystore::{passphrase, write_private, Sealed};
+use crate::store::{public_keys, ContractStore};
+use crate::transaction::{InteractionTx, PublicKeyTx, RawTx, SourceTx, ZkParamsTx};
+use async_trait::async_trait;
 use reqwest;
 use ring::digest::{Context, SHA256};
 use serde_json::{json, Value};
 use std::fmt::Write;
+use std::fs;
 use std::str::FromStr;
 use std::time::Duration;
 use std::time::SystemTime;
 use std::{path::PathBuf, time::UNIX_EPOCH};
-use tokio::fs;
 use url::Url;
 
 pub struct Ar {
     client: Arweave,
+    gateway: Url,
+    signer: EthSigner,
 }
 
+/// What a transaction holds, each kind is published under its own `App-Name`
 #[derive(PartialEq, Copy, Clone)]
-enum ContractType {
+pub enum ContractType {
     Source,
     Interaction,
     ZkSnark,
+    PublicKey,
 }
 
 impl Ar {
-    pub async fn new(path: String) -> Self {
-        let arweave = Arweave::from_keypair_path(
-            PathBuf::from(path),
-            Url::from_str("http://arweave.net").unwrap(),
-        )
-        .await
-        .unwrap();
-
-        Ar { client: arweave }
+    // uses the gateway and keyfile of the config, the keyfile may be encrypted with seal-keyfile
+    pub async fn new(config: &Config, signer: EthSigner) -> Result<Self, HarpocratesError> {
+        let sealed = match Sealed::read(&config.keyfile) {
+            Ok(sealed) => sealed,
+            Err(_) => {
+                return Ar::with_gateway(
+                    config.keyfile.display().to_string(),
+                    config.gateway.clone(),
+                    signer,
+                )
+                .await
+            }
+        };
+
+        // arloader only loads keyfiles from disk, the plaintext is removed as soon as it is loaded
+        let jwk = sealed.open(&passphrase("Passphrase of the Arweave keyfile: ", false)?)?;
+        fs::create_dir_all(&config.cache_dir)
+            .map_err(HarpocratesError::cache(&config.cache_dir))?;
+        let path = config.cache("arweave-keyfile.json");
+        write_private(&path, &jwk)?;
+        let ar = Ar::with_gateway(path.display().to_string(), config.gateway.clone(), signer).await;
+        fs::remove_file(&path).map_err(HarpocratesError::cache(&path))?;
+        ar
     }
 
-    // {
-    //     "App-Name": "harpocrates",
-    //     "App-Version": "0.0.1",
-    //     "Contract": "0x...",
-    //     "Content-Type": "application/json",
-    //     "Sunscreen-Version": "0.0.1",
-    //     "Validity-Proof": "ZkSnark/circom@2.0.8/snarkjs@0.4.27",
-    //     "Unix-Time": "134546456456",
-    //     "Input": "{}"
-    // }
-    // Returns Arweave TXID and contract TXID
-    pub async fn deploy_contract(
-        &self,
-        contract_data: &str,
-    ) -> Result<(String, String), Box<dyn std::error::Error>> {
-        let unix_timestamp = get_unix_timestamp();
-        // contract id is only the hash of the data and a unix timestamp -- this is only a POC - not secure as someone can change the unix timestamp
-        let mut id_data = contract_data.clone().to_string();
-        id_data.push_str(&unix_timestamp);
-
-        let contract_id = Base64(sha_256(id_data.as_bytes()).to_vec()).to_string();
-
-        let action = r#"{"action":"deploy", arguments: []}"#;
-
-        let (account, sig) = get_eth_metadata(&contract_data.as_bytes().to_vec()).await?;
-
-        let tags = self.create_tags(
-            &contract_id,
-            &unix_timestamp,
-            action,
-            ContractType::Source,
-            &account,
-            &sig,
-        );
-
-        let mut tx = self
-            .client
-            .create_transaction(
-                contract_data.as_bytes().to_vec(),
-                Some(tags),
-                None,
-                (60000000, 60000000),
-                false,
-            )
+    // talks to any gateway implementing the arweave HTTP + GraphQL API, such as the local one
+    pub async fn with_gateway(
+        path: String,
+        gateway: Url,
+        signer: EthSigner,
+    ) -> Result<Self, HarpocratesError> {
+        let arweave = Arweave::from_keypair_path(PathBuf::from(&path), gateway.clone())
             .await
-            .unwrap();
-
-        tx = self.client.sign_transaction(tx).unwrap();
-
-        let _res = self.client.post_transaction(&tx).await?;
+            .map_err(|e| HarpocratesError::Key(format!("cannot load keyfile {}: {}", path, e)))?;
 
-        Ok((tx.id.to_string(), contract_id))
-    }
-
-    pub async fn deploy_zksnark(
-        &self,
-        contract_id: &str,
-        contract_data: Vec<u8>,
-    ) -> Result<(String, String), Box<dyn std::error::Error>> {
-        let unix_timestamp = get_unix_timestamp();
-        // contract id is only the hash of the data and a unix timestamp -- this is only a POC - not secure as someone can change the unix timestamp
-
-        let action = r#"{"action":"zk_snark", arguments: []}"#;
-
-        let (account, sig) = get_eth_metadata(&contract_data).await?;
-
-        let tags = self.create_tags(
-            &contract_id,
-            &unix_timestamp,
-            action,
-            ContractType::ZkSnark,
-            &account,
-            &sig,
-        );
-
-        let mut tx = self
-            .client
-            .create_transaction(contract_data, Some(tags), None, (60000000, 60000000), false)
-            .await
-            .unwrap();
-
-        tx = self.client.sign_transaction(tx).unwrap();
-
-        let _res = self.client.post_transaction(&tx).await?;
-
-        Ok((tx.id.to_string(), contract_id.to_string()))
+        Ok(Ar {
+            client: arweave,
+            gateway,
+            signer,
+        })
     }
 
     fn create_tags(
         &self,
         contract_id: &str,
         unix_timestamp: &str,
-        action: &str,
+        input: &Input,
         contract_type: ContractType,
         eth_address: &str,
         eth_sig: &str,
-    ) -> Vec<Tag<Base64>> {
-        let app = get_app_name(contract_type);
-        vec![
-            Tag::<Base64>::from_utf8_strs("App-Name", &app).unwrap(),
-            Tag::<Base64>::from_utf8_strs("App-Version", "0.0.1").unwrap(),
-            Tag::<Base64>::from_utf8_strs("Contract", &contract_id).unwrap(),
-            Tag::<Base64>::from_utf8_strs("Content-Type", "application/json").unwrap(),
-            Tag::<Base64>::from_utf8_strs("Sunscreen-Version", "0.6.1").unwrap(),
-            Tag::<Base64>::from_utf8_strs("Validity-Proof", "ZkSnark/circom@2.0.8/snarkjs@0.4.27")
-                .unwrap(),
-            Tag::<Base64>::from_utf8_strs("Unix-Time", &unix_timestamp).unwrap(),
-            Tag::<Base64>::from_utf8_strs("Input", &action).unwrap(),
-            Tag::<Base64>::from_utf8_strs("Eth-Address", eth_address).unwrap(),
-            Tag::<Base64>::from_utf8_strs("Eth-Signature", eth_sig).unwrap(),
-        ]
+    ) -> Result<Vec<Tag<Base64>>, HarpocratesError> {
+        tag_pairs(
+            contract_id,
+            unix_timestamp,
+            input,
+            contract_type,
+            eth_address,
+            eth_sig,
+        )
+        .iter()
+        .map(|(name, value)| Ok(Tag::<Base64>::from_utf8_strs(name, value)?))
+        .collect()
     }
 
-    pub async fn initialize_state(
+    // creates, signs and posts a transaction, returns its id
+    async fn post(
         &self,
-        contract_id: &str,
-        initial_state: String,
-    ) -> Result<(String, String), Box<dyn std::error::Error>> {
-        let unix_timestamp = get_unix_timestamp();
-
-        let action = r#"{"action":"init_state", arguments: []}"#;
-        let (account, sig) = get_eth_metadata(&initial_state.as_bytes().to_vec()).await?;
-
-        let tags = self.create_tags(
-            &contract_id,
-            &unix_timestamp,
-            action,
-            ContractType::Interaction,
-            &account,
-            &sig,
-        );
-
-        let mut tx = self
+        data: Vec<u8>,
+        tags: Vec<Tag<Base64>>,
+    ) -> Result<String, HarpocratesError> {
+        let tx = self
             .client
-            .create_transaction(
-                initial_state.as_bytes().to_vec(),
-                Some(tags),
-                None,
-                (60000000, 60000000),
-                false,
-            )
-            .await
-            .unwrap();
+            .create_transaction(data, Some(tags), None, (60000000, 60000000), false)
+            .await?;
 
-        tx = self.client.sign_transaction(tx).unwrap();
+        let tx = self.client.sign_transaction(tx)?;
 
-        let _res = self.client.post_transaction(&tx).await?;
+        self.client.post_transaction(&tx).await?;
 
-        Ok((tx.id.to_string(), contract_id.to_string()))
+        Ok(tx.id.to_string())
     }
+}
 
-    pub async fn vote(
+#[async_trait(?Send)]
+impl ContractStore for Ar {
+    // {
+    //     "App-Name": "harpocrates",
+    //     "App-Version": "0.0.1",
+    //     "Contract": "0x...",
+    //     "Content-Type": "application/json",
+    //     "Sunscreen-Version": "0.0.1",
+    //     "Validity-Proof": "ZkSnark/circom@2.0.8/snarkjs@0.4.27",
+    //     "Unix-Time": "134546456456",
+    //     "Input": "{\"action\":\"deploy\",\"arguments\":[]}"
+    // }
+    async fn publish_tagged(
         &self,
         contract_id: &str,
-        vote_data: String,
-    ) -> Result<(String, String), Box<dyn std::error::Error>> {
+        input: &Input,
+        contract_type: ContractType,
+        data: Vec<u8>,
+        eth_address: &str,
+        eth_signature: &str,
+    ) -> Result<String, HarpocratesError> {
         let unix_timestamp = get_unix_timestamp();
 
-        // todo, currently we don't send out multiple votes, so no arguments of last votes
-        let action = r#"{"action":"vote", arguments: []}"#;
-        let (account, sig) = get_eth_metadata(&vote_data.as_bytes().to_vec()).await?;
-
         let tags = self.create_tags(
-            &contract_id,
+            contract_id,
             &unix_timestamp,
-            action,
-            ContractType::Interaction,
-            &account,
-            &sig,
-        );
+            input,
+            contract_type,
+            eth_address,
+            eth_signature,
+        )?;
 
-        let mut tx = self
-            .client
-            .create_transaction(
-                vote_data.as_bytes().to_vec(),
-                Some(tags),
-                None,
-                (60000000, 60000000),
-                false,
-            )
-            .await
-            .unwrap();
-
-        tx = self.client.sign_transaction(tx).unwrap();
-
-        let _res = self.client.post_transaction(&tx).await?;
-
-        Ok((tx.id.to_string(), contract_id.to_string()))
+        self.post(data, tags).await
     }
 
-    pub async fn fetch_latest_state(
+    async fn fetch_latest_state(
         &self,
         contract_id: String,
-    ) -> Result<(Vec<Value>, Vec<Value>), Error> {
-        let source = graphql_query(&contract_id, ContractType::Source)
-            .await
-            .unwrap();
+    ) -> Result<(Vec<SourceTx>, Vec<InteractionTx>), HarpocratesError> {
+        let source = self.fetch_source(contract_id.clone()).await?;
 
-        let interactions = graphql_query(&contract_id, ContractType::Interaction)
-            .await
-            .unwrap();
-
-        fs::write(
-            "./.cache/transactions.json",
-            json!({"source": source, "interactions":interactions}).to_string(),
-        )
-        .await
-        .unwrap();
+        let interactions = graphql_query(&self.gateway, &contract_id, ContractType::Interaction)
+            .await?
+            .into_iter()
+            .map(InteractionTx::from)
+            .collect();
 
         Ok((source, interactions))
     }
-    pub async fn fetch_zk(&self, contract_id: String) -> Result<Vec<u8>, Error> {
-        let zk_snark = zk_query(&contract_id, ContractType::ZkSnark).await.unwrap();
 
-        fs::write("./.cache/zksnark.bin", &zk_snark).await.unwrap();
+    async fn fetch_source(&self, contract_id: String) -> Result<Vec<SourceTx>, HarpocratesError> {
+        graphql_query(&self.gateway, &contract_id, ContractType::Source)
+            .await?
+            .into_iter()
+            .map(SourceTx::try_from)
+            .collect()
+    }
+
+    async fn fetch_zk_params(
+        &self,
+        contract_id: String,
+    ) -> Result<Vec<ZkParamsTx>, HarpocratesError> {
+        Ok(
+            graphql_query(&self.gateway, &contract_id, ContractType::ZkSnark)
+                .await?
+                .into_iter()
+                .map(ZkParamsTx::from)
+                .collect(),
+        )
+    }
 
-        Ok(zk_snark)
+    async fn fetch_public_keys(
+        &self,
+        contract_id: String,
+    ) -> Result<Vec<PublicKeyTx>, HarpocratesError> {
+        Ok(public_keys(
+            graphql_query(&self.gateway, &contract_id, ContractType::PublicKey).await?,
+        ))
     }
 
-    pub async fn wait_till_mined(&self, tx_id: &str) -> Result<(), Error> {
-        let id = Base64::from_str(&tx_id).unwrap();
+    async fn wait_till_mined(&self, tx_id: &str) -> Result<(), HarpocratesError> {
+        let id = Base64::from_str(&tx_id)
+            .map_err(|e| HarpocratesError::Gateway(format!("invalid tx id {}: {}", tx_id, e)))?;
 
-        let mut status = self.client.get_status(&id).await.unwrap();
+        let mut status = self.client.get_status(&id).await?;
 
         while status.status != StatusCode::Confirmed {
             tokio::time::sleep(Duration::from_secs(5)).await;
-            status = self.client.get_status(&id).await.unwrap();
+            status = self.client.get_status(&id).await?;
         }
         Ok(())
     }
+
+    async fn eth_address(&self) -> Result<String, HarpocratesError> {
+        self.signer.eth_address().await
+    }
+
+    async fn eth_sign(&self, data: &[u8]) -> Result<(String, String), HarpocratesError> {
+        get_eth_metadata(&self.signer, &data.to_vec()).await
+    }
 }
 
-fn get_unix_timestamp() -> String {
+pub(crate) fn get_unix_timestamp() -> String {
     let start = SystemTime::now();
-    let since_the_epoch = start.duration_since(UNIX_EPOCH).unwrap();
+    // the clock can't be set before 1970
+    let since_the_epoch = start.duration_since(UNIX_EPOCH).unwrap_or_default();
     since_the_epoch.as_secs().to_string()
 }
 
@@ -299,6 +252,7 @@ const QUERY: &str = r#"query Interactions ($app: String!, $block_min: Int, $cont
         node {
           id
           block {
+            height
             timestamp
           }
           owner {
@@ -316,128 +270,220 @@ const QUERY: &str = r#"query Interactions ($app: String!, $block_min: Int, $cont
   }"#;
 
 fn get_record(value: &Value) -> Value {
-    let obj = value.as_object().unwrap();
     json!({
-      "cursor": obj["cursor"],
-      "id": obj["node"]["id"],
-      "owner": obj["node"]["owner"]["address"],
-      "tags": obj["node"]["tags"],
+      "cursor": value["cursor"],
+      "id": value["node"]["id"],
+      "owner": value["node"]["owner"]["address"],
+      "block": value["node"]["block"],
+      "tags": value["node"]["tags"],
     })
 }
 
-fn get_app_name(contract_type: ContractType) -> String {
+// name and value of every tag attached to a harpocrates transaction
+pub(crate) fn tag_pairs(
+    contract_id: &str,
+    unix_timestamp: &str,
+    input: &Input,
+    contract_type: ContractType,
+    eth_address: &str,
+    eth_sig: &str,
+) -> Vec<(String, String)> {
+    let app = get_app_name(contract_type);
+    let input = input.tag();
+    let tags = vec![
+        ("App-Name", app.as_str()),
+        ("App-Version", "0.0.1"),
+        ("Contract", contract_id),
+        ("Content-Type", "application/json"),
+        ("Sunscreen-Version", "0.6.1"),
+        ("Validity-Proof", "ZkSnark/circom@2.0.8/snarkjs@0.4.27"),
+        ("Unix-Time", unix_timestamp),
+        ("Input", input.as_str()),
+        ("Eth-Address", eth_address),
+        ("Eth-Signature", eth_sig),
+    ];
+    tags.into_iter()
+        // anonymous votes don't say who sent them
+        .filter(|(name, value)| !(name.starts_with("Eth-") && value.is_empty()))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+pub(crate) fn get_app_name(contract_type: ContractType) -> String {
     let app = match contract_type {
         ContractType::Source => "harpocrates-source",
         ContractType::Interaction => "harpocrates-interactions",
         ContractType::ZkSnark => "harpocrates-zksnark",
+        ContractType::PublicKey => "harpocrates-public-key",
     };
 
     app.to_string()
 }
 
 async fn graphql_query(
+    gateway: &Url,
     contract_address: &str,
     contract_type: ContractType,
-) -> Result<Vec<Value>, Error> {
-    let mut values = fetch(contract_address, contract_type).await.unwrap();
+) -> Result<Vec<RawTx>, HarpocratesError> {
+    let values = fetch(gateway, contract_address, contract_type).await?;
 
-    for v in values.iter_mut() {
-        let resp = reqwest::get(format!(
-            "https://arweave.net/{}/data.json",
-            v["id"].clone().as_str().unwrap()
-        ))
-        .await
-        .unwrap()
-        .json::<Value>()
-        .await
-        .unwrap();
-        v.as_object_mut().unwrap().insert("data".to_string(), resp);
+    let mut txs = Vec::with_capacity(values.len());
+    for v in values.iter() {
+        txs.push(fetch_data(gateway, v).await?);
     }
 
-    Ok(values)
+    Ok(txs)
 }
 
-async fn fetch(contract_address: &str, contract_type: ContractType) -> Result<Vec<Value>, Error> {
-    let app = get_app_name(contract_type);
-    let mut values: Vec<Value> = Vec::new();
+// downloads the data of a record returned by `fetch`
+async fn fetch_data(gateway: &Url, record: &Value) -> Result<RawTx, HarpocratesError> {
+    let resp = reqwest::get(data_url(gateway, record)?)
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    RawTx::from_record(record, resp.to_vec())
+}
+
+// the gateway url serving the data of a record returned by `fetch`
+fn data_url(gateway: &Url, record: &Value) -> Result<Url, HarpocratesError> {
+    let id = record["id"]
+        .as_str()
+        .ok_or_else(|| HarpocratesError::Gateway(format!("record without an id: {}", record)))?;
+    Ok(gateway.join(&format!("{}/data.json", id))?)
+}
+
+// one page of results, starting right after `cursor`
+async fn fetch_page(
+    graphql: &Url,
+    app: &str,
+    contract_address: &str,
+    cursor: &str,
+) -> Result<Vec<Value>, HarpocratesError> {
     let resp = reqwest::Client::new()
-        .post("https://arweave.net/graphql")
-        .json(&json!({ "query": QUERY, "operationName": "Interactions", "variables": json!({"app": app, "block_min":1, "contract_address":contract_address,"follow_cursor": ""})}))
+        .post(graphql.clone())
+        .json(&json!({ "query": QUERY, "operationName": "Interactions", "variables": json!({"app": app, "block_min":1, "contract_address":contract_address,"follow_cursor": cursor})}))
         .send()
-        .await
-        .unwrap()
+        .await?
+        .error_for_status()?
         .json::<Value>()
-        .await
-        .unwrap();
+        .await?;
 
-    let mut transactions: Vec<Value> = resp.as_object().unwrap()["data"]["transactions"]["edges"]
+    let edges = resp["data"]["transactions"]["edges"]
         .as_array()
-        .unwrap()
-        .iter()
-        .map(get_record)
-        .collect();
+        .ok_or_else(|| {
+            HarpocratesError::Gateway(format!("unexpected graphql response: {}", resp))
+        })?;
+
+    Ok(edges.iter().map(get_record).collect())
+}
+
+async fn fetch(
+    gateway: &Url,
+    contract_address: &str,
+    contract_type: ContractType,
+) -> Result<Vec<Value>, HarpocratesError> {
+    let app = get_app_name(contract_type);
+    let graphql = gateway.join("graphql")?;
+    let mut values: Vec<Value> = Vec::new();
+
+    let mut transactions = fetch_page(&graphql, &app, contract_address, "").await?;
 
     while transactions.len() > 0 {
         values.append(&mut transactions);
-        let cursor = values.last().unwrap().as_object().unwrap()["cursor"]
+        let cursor = values[values.len() - 1]["cursor"]
             .as_str()
-            .unwrap();
-        let resp = reqwest::Client::new()
-            .post("https://arweave.net/graphql")
-            .json(&json!({ "query": QUERY, "operationName": "Interactions", "variables": json!({"app": app, "block_min":1, "contract_address":contract_address, "follow_cursor": cursor})}))
-            .send()
-            .await
-            .unwrap()
-            .json::<Value>()
-            .await
-            .unwrap();
+            .ok_or_else(|| HarpocratesError::Gateway("graphql edge without a cursor".to_string()))?
+            .to_string();
 
-        transactions = resp.as_object().unwrap()["data"]["transactions"]["edges"]
-            .as_array()
-            .unwrap()
-            .iter()
-            .map(get_record)
-            .collect();
+        transactions = fetch_page(&graphql, &app, contract_address, &cursor).await?;
     }
     Ok(values)
 }
 
-async fn zk_query(contract_address: &str, contract_type: ContractType) -> Result<Vec<u8>, Error> {
-    let values = fetch(contract_address, contract_type).await.unwrap();
-
-    let resp = reqwest::get(format!(
-        "https://arweave.net/{}/data.json",
-        values[0]["id"].clone().as_str().unwrap()
-    ))
-    .await
-    .unwrap()
-    .bytes()
-    .await
-    .unwrap();
-
-    return Ok(resp.to_vec());
-}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::action::Action;
+    use crate::gateway::{LocalGateway, Mining};
+    use crate::testing::{signer, TEST_ETH_KEY};
+
+    async fn local_ar(gateway: &LocalGateway) -> Ar {
+        Ar::with_gateway(
+            "./tests/fixtures/test-wallet.json".to_string(),
+            gateway.url(),
+            signer(TEST_ETH_KEY),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn it_rejects_invalid_hex() {
+        assert_eq!(decode_hex("00ff").unwrap(), vec![0, 255]);
+        for invalid in ["0", "0g", "é0", "0é0", "+f", "-1"] {
+            assert!(matches!(
+                decode_hex(invalid),
+                Err(HarpocratesError::Serialization(_))
+            ));
+        }
+    }
 
     #[tokio::test]
     async fn it_waits_till_mined() -> Result<(), Box<dyn std::error::Error>> {
-        let ar = Ar::new("./arweave-keyfile.json".to_string()).await;
-        let _res = ar
-            .wait_till_mined("vPxIKj-kq7l1lXhVwJpNDIa1Xsz2lHR3TnpUDAHM4aQ")
-            .await;
+        let gateway = LocalGateway::start(0, Mining::Interval(Duration::from_secs(1))).await?;
+        let ar = local_ar(&gateway).await;
+
+        let (tx_id, _) = ar.deploy_contract("{}").await?;
+
+        ar.wait_till_mined(&tx_id).await?;
+        assert!(gateway.height() > 0);
         Ok(())
     }
 
     #[tokio::test]
     async fn graphql_query_test() -> Result<(), Box<dyn std::error::Error>> {
-        let _res = zk_query(
-            "28dygSSTZsbHVeOmEO69B0bS7aVzYWr2pFM1HCdosGg",
-            ContractType::ZkSnark,
-        )
-        .await
-        .unwrap();
+        let gateway = LocalGateway::start(0, Mining::Instant).await?;
+        let ar = local_ar(&gateway).await;
+
+        let (_, contract_id) = ar.deploy_contract(r#"{"source": true}"#).await?;
+        ar.deploy_zksnark(&contract_id, vec![1, 2, 3]).await?;
+
+        // more interactions than fit in a single page
+        for i in 0..12 {
+            ar.vote(&contract_id, json!({ "vote": i }).to_string())
+                .await?;
+        }
+
+        let (source, interactions) = ar.fetch_latest_state(contract_id.clone()).await?;
+        let zk = ar.fetch_zk(&source[0]).await?;
+        assert_eq!(zk.data, vec![1, 2, 3]);
+        assert_eq!(source.len(), 1);
+        assert_eq!(source[0].source, r#"{"source": true}"#);
+        assert_eq!(source[0].meta.contract, contract_id);
+        assert_eq!(source[0].meta.input.action, Action::Deploy);
+        // votes say which params they are proven with
+        assert_eq!(interactions[0].meta.input.validity_proof, Some(zk.meta.id));
+        assert!(source[0].meta.block.is_some());
+        assert_eq!(
+            source[0].meta.data_sha256,
+            encode_hex(&sha_256(r#"{"source": true}"#.as_bytes()))
+        );
+        // every page was fetched, in order
+        assert_eq!(
+            interactions
+                .iter()
+                .map(|i| i.meta.cursor.parse::<usize>().unwrap())
+                .collect::<Vec<_>>(),
+            (2..14).collect::<Vec<_>>()
+        );
+
+        // the tags were signed by the test key, and a tampered record no longer checks out
+        source[0].meta.verify_eth_signature()?;
+        let mut tampered = interactions[0].meta.clone();
+        tampered.data_sha256 = encode_hex(&sha_256(b"{}"));
+        assert!(tampered.verify_eth_signature().is_err());
         Ok(())
     }
 }
@@ -450,24 +496,43 @@ pub fn encode_hex(bytes: &[u8]) -> String {
     s
 }
 
+pub fn decode_hex(s: &str) -> Result<Vec<u8>, HarpocratesError> {
+    // it is sliced by byte, and from_str_radix would take a sign: only hex digits go through
+    if !s.bytes().all(|b| b.is_ascii_hexdigit()) || s.len() % 2 != 0 {
+        return Err(HarpocratesError::Serialization(format!(
+            "invalid hex {}",
+            s
+        )));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&s[i..i + 2], 16)
+                .map_err(|_| HarpocratesError::Serialization(format!("invalid hex {}", s)))
+        })
+        .collect()
+}
+
 pub async fn get_eth_metadata(
+    signer: &EthSigner,
     data: &Vec<u8>,
-) -> Result<(String, String), Box<dyn std::error::Error>> {
+) -> Result<(String, String), HarpocratesError> {
     // to show you own your ethereum address, you need to sign a message
-    // the message is the concatenation of your address + hash of the data being deployed
+    signer.sign_with(|account| eth_message(account, data)).await
+}
 
+// the message is the concatenation of your address + hash of the data being deployed
+pub fn eth_message(account: &str, data: &[u8]) -> String {
     // we get the hash
-    let hash = sha_256(&data);
-
-    let c = EthClient::new().await?;
+    let hash = sha_256(data);
 
-    let account = c.account();
-    let account_b = account.as_bytes();
-
-    let mut iter = account_b.into_iter().chain(&hash);
-    let result = [(); 74].map(|_| iter.next().unwrap().to_owned());
+    eth_message_for_hash(account, &hash)
+}
 
-    let hashed_message = encode_hex(&sha_256(&result));
+// same message, when only the sha256 of the data is known
+pub fn eth_message_for_hash(account: &str, data_hash: &[u8]) -> String {
+    let mut message = account.as_bytes().to_vec();
+    message.extend_from_slice(data_hash);
 
-    c.get_sig(&hashed_message).await
+    encode_hex(&sha_256(&message))
 }
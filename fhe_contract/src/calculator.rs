@@ -1,67 +1,100 @@
-use crate::contract::add_vote;
-use sunscreen::{
-    types::bfv::Signed, Application, Ciphertext, Error, FheProgramInput, PrivateKey, PublicKey,
-    Runtime,
-};
-// todo add the inputs
-pub fn calculate<I>(
+use crate::contract::HarpocratesContract;
+use crate::error::HarpocratesError;
+use sunscreen::{Application, Ciphertext, PrivateKey, PublicKey, Runtime};
+
+// runs the contract step on every (state, input) pair, both must be `contract.width()` long
+pub fn calculate<C: HarpocratesContract>(
+    contract: &C,
+    app: &Application,
+    pk: &PublicKey,
+    state: Vec<Ciphertext>,
+    input: Vec<Ciphertext>,
+) -> Result<Vec<Ciphertext>, HarpocratesError> {
+    run_program(app, &contract.step(), contract.width(), pk, state, input)
+}
+
+// takes an input that went through `calculate` back out of the state
+pub fn revert<C: HarpocratesContract>(
+    contract: &C,
+    app: &Application,
+    pk: &PublicKey,
+    state: Vec<Ciphertext>,
+    input: Vec<Ciphertext>,
+) -> Result<Vec<Ciphertext>, HarpocratesError> {
+    let undo = contract.undo().ok_or_else(|| {
+        HarpocratesError::Contract(format!("contract {} can't undo inputs", contract.name()))
+    })?;
+    run_program(app, &undo, contract.width(), pk, state, input)
+}
+
+fn run_program(
     app: &Application,
+    name: &str,
+    width: usize,
     pk: &PublicKey,
-    arguments: Vec<I>,
-) -> Result<Ciphertext, Error>
-where
-    I: Into<FheProgramInput>,
-{
+    state: Vec<Ciphertext>,
+    input: Vec<Ciphertext>,
+) -> Result<Vec<Ciphertext>, HarpocratesError> {
+    // zipping them would silently drop the values of the longest
+    if state.len() != width || input.len() != width {
+        return Err(HarpocratesError::Contract(format!(
+            "the state has {} values and the input {}, the contract takes {}",
+            state.len(),
+            input.len(),
+            width
+        )));
+    }
+
     let runtime = Runtime::new(app.params())?;
 
-    let final_result = runtime.run(app.get_program(add_vote).unwrap(), arguments, &pk)?;
+    let program = app.get_program(name).ok_or_else(|| {
+        HarpocratesError::Contract(format!(
+            "program {} is not part of the compiled contract",
+            name
+        ))
+    })?;
+
+    let mut new_state = Vec::with_capacity(state.len());
+    for (curr, i) in state.into_iter().zip(input) {
+        let result = runtime.run(program, vec![curr, i], &pk)?;
+        new_state.push(result[0].clone());
+    }
 
-    Ok(final_result[0].clone())
+    Ok(new_state)
 }
 
-pub fn get_initial_state(contract_json: &str, pk: &PublicKey) -> Result<String, Error> {
-    let app: Application = serde_json::from_str(&contract_json).unwrap();
-
+pub fn get_initial_state<C: HarpocratesContract>(
+    contract: &C,
+    app: &Application,
+    pk: &PublicKey,
+) -> Result<String, HarpocratesError> {
     let runtime = Runtime::new(app.params())?;
 
-    let init_state = runtime.encrypt(
-        [
-            Signed::from(0),
-            Signed::from(0),
-            Signed::from(0),
-            Signed::from(0),
-            Signed::from(0),
-            Signed::from(0),
-            Signed::from(0),
-            Signed::from(0),
-            Signed::from(0),
-            Signed::from(0),
-        ],
-        &pk,
-    )?;
-
-    let ser_json = serde_json::to_string(&init_state).unwrap();
+    let init_state = contract.initial_state(&runtime, &pk)?;
+
+    let ser_json = serde_json::to_string(&init_state)?;
     Ok(ser_json)
 }
 
-pub fn decrypt(
+pub fn decrypt<C: HarpocratesContract>(
+    contract: &C,
     app: &Application,
-    to_decrypt: Ciphertext,
+    to_decrypt: &[Ciphertext],
     sk: &PrivateKey,
-) -> Result<[Signed; 10], Error> {
+) -> Result<C::Output, HarpocratesError> {
     let runtime = Runtime::new(app.params())?;
 
-    let c: [Signed; 10] = runtime.decrypt(&to_decrypt, &sk)?;
-    Ok(c)
+    Ok(contract.decode_output(&runtime, &sk, to_decrypt)?)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use sunscreen::types::bfv::Signed;
+    use crate::compiler::compile;
+    use crate::contract::VoteContract;
 
     #[test]
-    fn it_works() -> Result<(), Error> {
+    fn it_works() -> Result<(), HarpocratesError> {
         let contract_json = std::fs::read_to_string("./.cache/add_vote_params.json")
             .expect("Should have been able to read the file");
 
@@ -71,51 +104,72 @@ mod tests {
 
         let (counter_pk, counter_sk) = runtime.generate_keys()?;
 
-        let init_state = runtime.encrypt(
-            [
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-            ],
-            &counter_pk,
-        )?;
-
-        let alice_vote = runtime.encrypt(
-            [
-                Signed::from(1),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-            ],
-            &counter_pk,
-        )?;
+        let contract = VoteContract::new(3);
+
+        let init_state = contract.initial_state(&runtime, &counter_pk)?;
+
+        let alice_vote = contract.encode_input(&runtime, &counter_pk, 0)?;
 
         let app: Application = serde_json::from_str(&contract_json).unwrap();
 
-        let res = calculate(&app, &counter_pk, vec![init_state, alice_vote])?;
+        let res = calculate(&contract, &app, &counter_pk, init_state, alice_vote)?;
 
-        let final_tally: [Signed; 10] = runtime.decrypt(&res, &counter_sk)?;
+        let final_tally = decrypt(&contract, &app, &res, &counter_sk)?;
 
+        assert_eq!(final_tally.len(), 3);
         assert_eq!(final_tally[0].to_string(), "1".to_string());
+        assert_eq!(final_tally[1].to_string(), "0".to_string());
+
+        Ok(())
+    }
 
+    #[test]
+    fn it_reverts_an_input() -> Result<(), HarpocratesError> {
+        let contract = VoteContract::new(3);
+        let app: Application = serde_json::from_str(&compile(&contract)?)?;
+        let runtime = Runtime::new(app.params())?;
+        let (pk, sk) = runtime.generate_keys()?;
+
+        let mut state = contract.initial_state(&runtime, &pk)?;
+        for index in [1, 2] {
+            let vote = contract.encode_input(&runtime, &pk, index)?;
+            state = calculate(&contract, &app, &pk, state, vote)?;
+        }
+
+        // the vote for 1 is taken back out
+        let vote = contract.encode_input(&runtime, &pk, 1)?;
+        state = revert(&contract, &app, &pk, state, vote)?;
+
+        let tally = decrypt(&contract, &app, &state, &sk)?;
+        assert_eq!(
+            tally.iter().map(|t| t.to_string()).collect::<Vec<_>>(),
+            vec!["0", "0", "1"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_an_input_of_another_width() -> Result<(), HarpocratesError> {
+        let contract = VoteContract::new(3);
+        let app: Application = serde_json::from_str(&compile(&contract)?)?;
+        let runtime = Runtime::new(app.params())?;
+        let (pk, _) = runtime.generate_keys()?;
+
+        let state = contract.initial_state(&runtime, &pk)?;
+        let vote = VoteContract::new(2).encode_input(&runtime, &pk, 1)?;
+        assert!(matches!(
+            calculate(&contract, &app, &pk, state.clone(), vote.clone()),
+            Err(HarpocratesError::Contract(_))
+        ));
+        assert!(matches!(
+            revert(&contract, &app, &pk, state, vote),
+            Err(HarpocratesError::Contract(_))
+        ));
         Ok(())
     }
 
     #[test]
-    fn it_get_init_state() -> Result<(), Error> {
+    fn it_get_init_state() -> Result<(), HarpocratesError> {
         let contract_json = std::fs::read_to_string("./compiled_contract/add_vote_params.json")
             .expect("Should have been able to read the file");
 
@@ -125,7 +179,7 @@ mod tests {
 
         let (counter_pk, _) = runtime.generate_keys()?;
 
-        let _ = get_initial_state(&contract_json, &counter_pk);
+        let _ = get_initial_state(&VoteContract::default(), &app, &counter_pk);
 
         Ok(())
     }
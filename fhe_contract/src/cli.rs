@@ -1,33 +1,132 @@
 use clap::{Parser, Subcommand};
-use prettytable::{row, Cell, Row, Table};
-use serde::{Deserialize, Serialize};
-use sunscreen::types::bfv::Signed;
+use prettytable::{Cell, Row, Table};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use sunscreen::{Application, Ciphertext, PrivateKey, PublicKey, Runtime};
 
+use crate::allowlist::{
+    merkle_proof, merkle_root, normalize, read_addresses, read_commitments, Allowlist,
+    AllowlistUpdate, Members, MerkleProof,
+};
+use crate::anonymous::{field_to_string, parse_field, VoterIdentity};
+use crate::arweave::{decode_hex, encode_hex, sha_256, Ar};
 use crate::ascii;
-use crate::calculator::{calculate, decrypt, get_initial_state};
+use crate::calculator::{calculate, decrypt, get_initial_state, revert};
 use crate::compiler::compile;
-use crate::snarkjs::{generate_proof, generate_witness, verify_snark_proof};
+use crate::config::Config;
+use crate::contract::{
+    option_label, source_nonce, ContractSource, HarpocratesContract, VoteContract,
+};
+use crate::error::HarpocratesError;
+use crate::ethereum::EthSigner;
+use crate::gateway::{LocalGateway, Mining};
+use crate::groth16::{
+    anonymous_nullifier, check_verification_key, verify_anonymous_vote, verify_vote,
+    AnonymousStatement, BallotBinding, Verification, VoteProver, VoteStatement,
+    ANONYMOUS_PUBLIC_INPUTS, VOTE_CIRCUIT_OPTIONS, VOTE_PUBLIC_INPUTS,
+};
+use crate::keystore::{passphrase, write_private, Sealed};
+use crate::poll::{PollTime, PollWindow};
+use crate::profile::{ExportedKeys, DEFAULT_PROFILE};
+use crate::queue::{BallotQueue, StagedBallot};
+use crate::replay::{Ballots, Decision, Duplicate, DuplicatePolicy, Identity};
+use crate::store::{ContractStore, LocalStore};
+use crate::tally::{state_hash, PublishedResult};
+use crate::transaction::{
+    AggregateVote, AggregatedBallot, Interaction, InteractionTx, PublicKeyTx, SourceTx, TxMeta,
+    VoteData, ZKProof,
+};
 #[allow(unused)]
 use owo_colors::OwoColorize;
 use serde_json::{json, Value};
-use std::fs::{self, File};
-use std::io::prelude::*;
-use std::os::unix::prelude::PermissionsExt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+use zeroize::Zeroizing;
 /// Search for a pattern in a file and display the lines that contain it.
 #[derive(Parser, Debug)]
 #[clap(author="Dev43",name="harpocrates", version="0.0.1", about = Some("POC providing a Permanent Fully Homomorphic Encryption smart contract on Arweave."), long_about = None)]
 struct Cli {
     #[clap(subcommand)]
     command: Option<Commands>,
+
+    /// Publishes to and reads from this directory instead of Arweave
+    #[clap(long, value_parser, global = true)]
+    local_store: Option<PathBuf>,
+
+    /// Runs an in-memory Arweave gateway for the duration of the command and uses it instead of arweave.net
+    #[clap(long, value_parser, global = true)]
+    local_gateway: bool,
+
+    /// Signs the Eth-Signature tags with this private key instead of asking WalletConnect
+    #[clap(long, value_parser, global = true)]
+    eth_key: Option<String>,
+
+    /// TOML config file, defaults to $HARPOCRATES_CONFIG or ./harpocrates.toml
+    #[clap(long, value_parser, global = true)]
+    config: Option<PathBuf>,
+
+    /// Arweave gateway to use [env: HARPOCRATES_GATEWAY]
+    #[clap(long, value_parser, global = true)]
+    gateway: Option<Url>,
+
+    /// Arweave keyfile paying for the transactions [env: HARPOCRATES_KEYFILE]
+    #[clap(long, value_parser, global = true)]
+    keyfile: Option<PathBuf>,
+
+    /// Where the keys, transactions and zk params are kept [env: HARPOCRATES_CACHE_DIR]
+    #[clap(long, value_parser, global = true)]
+    cache_dir: Option<PathBuf>,
+
+    /// Directory holding circom/ [env: HARPOCRATES_ARTIFACTS_DIR]
+    #[clap(long, value_parser, global = true)]
+    artifacts_dir: Option<PathBuf>,
+
+    /// Keys to use, see `keys list` [env: HARPOCRATES_PROFILE]
+    #[clap(long, value_parser, global = true)]
+    profile: Option<String>,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
     #[clap(about = "creates the keys for a new user")]
     CreateNewUser {},
+    #[clap(about = "creates a secret identity to vote anonymously, and prints its commitment")]
+    CreateIdentity {},
+    #[clap(about = "encrypts an Arweave keyfile with a passphrase, to be used with --keyfile")]
+    SealKeyfile {
+        #[clap(value_parser)]
+        keyfile: PathBuf,
+
+        /// Where the encrypted keyfile is written
+        #[clap(value_parser)]
+        output: PathBuf,
+    },
     #[clap(about = "deploys the FHE contract to Arweave")]
-    Deploy {},
+    Deploy {
+        /// Number of propositions, at most 10 as the vote circuit has a fixed number of inputs
+        #[clap(long, value_parser, default_value_t = 10)]
+        options: usize,
+
+        /// File of the Eth addresses allowed to vote, one per line
+        #[clap(long, value_parser, conflicts_with = "allowlist-root")]
+        allowlist: Option<PathBuf>,
+
+        /// Only publishes the Merkle root of the allowed addresses, see `allowlist root`
+        #[clap(long, value_parser)]
+        allowlist_root: Option<String>,
+
+        /// File of the identity commitments allowed to vote anonymously, one per line
+        #[clap(long, value_parser, conflicts_with_all = &["allowlist", "allowlist-root"])]
+        anonymous_allowlist: Option<PathBuf>,
+
+        /// Votes mined before this are ignored, `height:<N>` or `timestamp:<UNIX>`
+        #[clap(long, value_parser)]
+        opens: Option<PollTime>,
+
+        /// Votes mined from this on are ignored, `height:<N>` or `timestamp:<UNIX>`
+        #[clap(long, value_parser)]
+        closes: Option<PollTime>,
+    },
     #[clap(about = "deploys all the information needed for ZKsnark to arweave")]
     InitZkProof {
         #[clap(value_parser)]
@@ -48,8 +147,21 @@ enum Commands {
         #[clap(value_parser)]
         contract_id: String,
     },
+    #[clap(about = "fetches the public key of the contract admin and saves it in the cache")]
+    FetchPk {
+        #[clap(value_parser)]
+        contract_id: String,
+    },
     #[clap(about = "computes the result of all the FHE transactions")]
-    ComputeLatest {},
+    ComputeLatest {
+        /// Which vote counts when an identity votes more than once
+        #[clap(long, value_enum, default_value = "first")]
+        duplicates: DuplicatePolicy,
+
+        /// What identifies a voter
+        #[clap(long, value_enum, default_value = "eth-address")]
+        identity: Identity,
+    },
     #[clap(about = "create and deploys a vote on the user's preferred proposition")]
     Vote {
         #[clap(value_parser)]
@@ -57,75 +169,259 @@ enum Commands {
 
         #[clap(value_parser)]
         number: usize,
+
+        /// Proof we are on the allowlist, see `allowlist proof`
+        #[clap(long, value_parser)]
+        membership_proof: Option<PathBuf>,
+
+        /// Stages the ballot in this queue instead of publishing it, see `aggregate`
+        #[clap(long, value_parser)]
+        queue: Option<PathBuf>,
+    },
+    #[clap(
+        about = "sums the ballots staged in a queue and signs the sum, the last voter to sign publishes it"
+    )]
+    Aggregate {
+        #[clap(value_parser)]
+        contract_id: String,
+
+        /// Directory the voters staged their ballots in
+        #[clap(long, value_parser)]
+        queue: PathBuf,
+    },
+    #[clap(about = "decrypts the tally and publishes it with what it was decrypted from")]
+    PublishResult {
+        #[clap(value_parser)]
+        contract_id: String,
+
+        /// Which vote counts when an identity votes more than once
+        #[clap(long, value_enum, default_value = "first")]
+        duplicates: DuplicatePolicy,
+
+        /// What identifies a voter
+        #[clap(long, value_enum, default_value = "eth-address")]
+        identity: Identity,
+    },
+    #[clap(
+        about = "checks the result published by the deployer is consistent with the interactions, it can't tell if it was decrypted correctly"
+    )]
+    CheckResult {
+        #[clap(value_parser)]
+        contract_id: String,
+    },
+    #[clap(about = "closes the poll, no vote counts after it, only its deployer can")]
+    Close {
+        #[clap(value_parser)]
+        contract_id: String,
+    },
+    #[clap(about = "manages who may vote on a contract")]
+    Allowlist {
+        #[clap(subcommand)]
+        command: AllowlistCommands,
+    },
+    #[clap(about = "manages the keys of each profile")]
+    Keys {
+        #[clap(subcommand)]
+        command: KeysCommands,
     },
     #[clap(about = "runs all the interactions in the correct order, also is interactive")]
     RunAll {},
 }
 
+#[derive(Subcommand, Debug)]
+enum KeysCommands {
+    #[clap(about = "lists the profiles and the contracts they are the admin of")]
+    List {},
+    #[clap(about = "writes the keys of a profile to a file, the secret key stays encrypted")]
+    Export {
+        #[clap(value_parser)]
+        profile: String,
+
+        #[clap(value_parser)]
+        file: PathBuf,
+    },
+    #[clap(about = "saves keys written by export as a new profile")]
+    Import {
+        #[clap(value_parser)]
+        profile: String,
+
+        #[clap(value_parser)]
+        file: PathBuf,
+    },
+    #[clap(about = "deletes a profile and its keys")]
+    Delete {
+        #[clap(value_parser)]
+        profile: String,
+
+        /// Also deletes a profile that is the admin of contracts, which can't be decrypted afterwards
+        #[clap(long, value_parser)]
+        force: bool,
+    },
+    #[clap(about = "makes a profile the admin of a contract, its keys decrypt the contract")]
+    Admin {
+        #[clap(value_parser)]
+        contract_id: String,
+
+        #[clap(value_parser)]
+        profile: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum AllowlistCommands {
+    #[clap(
+        about = "prints the Merkle root of a file of Eth addresses, to deploy with --allowlist-root"
+    )]
+    Root {
+        #[clap(value_parser)]
+        file: PathBuf,
+    },
+    #[clap(about = "saves the proof that an address is in a file of Eth addresses in the cache")]
+    Proof {
+        #[clap(value_parser)]
+        file: PathBuf,
+
+        #[clap(value_parser)]
+        address: String,
+    },
+    #[clap(
+        about = "lets more addresses (or identity commitments) vote on a contract, only its deployer can"
+    )]
+    Add {
+        #[clap(value_parser)]
+        contract_id: String,
+
+        #[clap(value_parser, required = true)]
+        addresses: Vec<String>,
+    },
+    #[clap(
+        about = "stops addresses (or identity commitments) from voting on a contract, only its deployer can"
+    )]
+    Remove {
+        #[clap(value_parser)]
+        contract_id: String,
+
+        #[clap(value_parser, required = true)]
+        addresses: Vec<String>,
+    },
+}
+
 #[derive(Serialize, Deserialize)]
 struct Keys {
     pub pk: String,
     pub sk: String,
 }
 
+// what fetch-latest caches for compute-latest
 #[derive(Serialize, Deserialize)]
 struct Transactions {
-    pub interactions: Vec<Value>,
-    pub source: Vec<Value>,
+    pub interactions: Vec<InteractionTx>,
+    pub source: Vec<SourceTx>,
+    /// The key votes are bound to, `None` if the deployer didn't publish a valid one
+    pub public_key: Option<PublicKeyTx>,
+    /// The transaction of the zk params votes are proven with, `None` if the deployer
+    /// didn't publish them
+    pub zk_params: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 struct ZkInfo {
     pub verification_key: Vec<u8>,
     pub vote_is_valid_0001_zkey: Vec<u8>,
-    pub generate_witness: Vec<u8>,
+    /// Only published for contracts deployed with `--anonymous-allowlist`
+    pub anonymous: Option<AnonymousZkInfo>,
 }
 
 #[derive(Serialize, Deserialize)]
-struct ZKProof {
-    pub proof: String,
-    pub public: String,
+struct AnonymousZkInfo {
+    pub verification_key: Vec<u8>,
+    pub vote_0001_zkey: Vec<u8>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct VoteData {
-    pub data: String,
-    pub zkp: ZKProof,
+fn write_to_file(config: &Config, name: String, data: String) -> Result<(), HarpocratesError> {
+    let path = config.cache(&name);
+    fs::write(&path, data.as_bytes()).map_err(HarpocratesError::cache(path))
 }
 
-fn write_to_file(name: String, data: String) -> Result<(), Box<dyn std::error::Error>> {
-    let mut file = File::create(format!("./.cache/{}", name))?;
-    file.write_all(data.as_bytes())?;
-    Ok(())
+fn create_cache_dir(config: &Config) -> Result<(), HarpocratesError> {
+    fs::create_dir_all(&config.cache_dir).map_err(HarpocratesError::cache(&config.cache_dir))
 }
 
-fn create_new_user() -> Result<(), Box<dyn std::error::Error>> {
-    let contract_json = compile().unwrap();
+fn create_new_user<C: HarpocratesContract>(
+    config: &Config,
+    contract: &C,
+) -> Result<(), HarpocratesError> {
+    let contract_json = compile(contract)?;
+
+    let app: Application = serde_json::from_str(&contract_json)?;
 
-    let app: Application = serde_json::from_str(&contract_json).unwrap();
+    let runtime = Runtime::new(app.params())?;
 
-    let runtime = Runtime::new(app.params()).unwrap();
+    // the keys may be the only ones able to decrypt a poll
+    let profiles = config.profiles();
+    if profiles.exists(&config.profile)? {
+        return Err(HarpocratesError::Key(format!(
+            "profile {} already has keys, pick another --profile",
+            config.profile
+        )));
+    }
 
-    let (pk, sk) = runtime.generate_keys().unwrap();
+    let (pk, sk) = runtime.generate_keys()?;
 
-    #[allow(unused)]
-    fs::create_dir("./.cache");
+    let dir = profiles.create(&config.profile)?;
 
-    write_to_file("pk.json".to_string(), (serde_json::to_string(&pk)).unwrap()).unwrap();
-    write_to_file("sk.json".to_string(), json!({ "sk": sk }).to_string()).unwrap();
+    let pk_path = dir.join("pk.json");
+    fs::write(&pk_path, serde_json::to_string(&pk)?).map_err(HarpocratesError::cache(&pk_path))?;
+    let raw = Zeroizing::new(json!({ "sk": sk }).to_string());
+    seal_secret_key(
+        &dir.join("sk.json"),
+        raw.as_bytes(),
+        "Passphrase to encrypt the secret key: ",
+    )?;
+
+    println!("Keys created for profile {}", config.profile);
     Ok(())
 }
 
-async fn deploy() -> Result<String, Box<dyn std::error::Error>> {
-    let contract_json = compile().unwrap();
+// the secret key is only written encrypted
+fn seal_secret_key(path: &Path, raw: &[u8], prompt: &str) -> Result<(), HarpocratesError> {
+    let passphrase = passphrase(prompt, true)?;
+    Sealed::seal(raw, &passphrase)?.write(path)
+}
 
-    let ar = crate::arweave::Ar::new("./arweave-keyfile.json".to_string()).await;
+#[derive(Deserialize)]
+struct SecretKeyFile {
+    sk: Vec<u8>,
+}
 
-    let res = ar.deploy_contract(&contract_json).await?;
+async fn deploy<C: HarpocratesContract + Serialize + Clone>(
+    config: &Config,
+    store: &dyn ContractStore,
+    contract: &C,
+    allowlist: Option<Allowlist>,
+    window: PollWindow,
+) -> Result<String, HarpocratesError> {
+    let contract_json = compile(contract)?;
+
+    // voters encrypt their ballots to our public key, so it is published along with the contract
+    let pk = read_key_file(&config.profiles().file(&config.profile, "pk.json")?)?;
+
+    // the source holds the compiled contract, the parameters it was deployed with, who may vote and when
+    let source = ContractSource {
+        app: serde_json::from_str(&contract_json)?,
+        contract: contract.clone(),
+        allowlist,
+        window,
+        nonce: source_nonce(),
+    };
+    let source_json = serde_json::to_string(&source)?;
+
+    let res = store.deploy_contract(&source_json).await?;
     let contract_id = res.1;
     let tx_id = res.0;
 
     write_to_file(
+        config,
         "deployment.json".to_string(),
         json!({"arweave_id": tx_id, "contract_id": contract_id}).to_string(),
     )?;
@@ -133,37 +429,70 @@ async fn deploy() -> Result<String, Box<dyn std::error::Error>> {
     println!("Deploy: Arweave Tx ID: {} ", tx_id);
 
     // we wait till mined (main txn for now)
-    let _mined_res = ar.wait_till_mined(&tx_id).await.unwrap();
+    store.wait_till_mined(&tx_id).await?;
 
     println!("Deploy: Contract inner ID: {} ", contract_id);
 
+    // our keys decrypt the contract, whatever --profile says later
+    config.profiles().set_admin(&contract_id, &config.profile)?;
+
+    let (pk_tx_id, _) = store.publish_pk(&contract_id, pk).await?;
+    println!("Deploy: Public key Arweave Tx ID: {} ", pk_tx_id);
+    store.wait_till_mined(&pk_tx_id).await?;
+
     Ok(contract_id)
 }
 
-async fn init_zk(id: &String) -> Result<(), Box<dyn std::error::Error>> {
+async fn init_zk<C: HarpocratesContract + DeserializeOwned>(
+    config: &Config,
+    store: &dyn ContractStore,
+    id: &String,
+) -> Result<(), HarpocratesError> {
     let contract_id = id.clone();
 
-    let ar = crate::arweave::Ar::new("./arweave-keyfile.json".to_string()).await;
+    let source: ContractSource<C> = fetch_source_tx(store, &contract_id).await?.contract()?;
+
+    let verification_key = read_file(&config.artifact("circom/verification_key.json"))?;
+    let vote_is_valid_0001_zkey = read_file(&config.artifact("circom/vote_is_valid_0001.zkey"))?;
+
+    // keys that don't match the circuits would make every vote invalid, for good
+    check_verification_key(
+        &String::from_utf8_lossy(&verification_key),
+        VOTE_PUBLIC_INPUTS,
+    )?;
 
-    let verification_key = read_file("./circom/verification_key.json").unwrap();
-    let vote_is_valid_0001_zkey = read_file("./circom/vote_is_valid_0001.zkey").unwrap();
-    let generate_witness = read_file("./bin/generate_witness/generate_witness").unwrap();
+    // the other contracts never take anonymous votes
+    let anonymous = match source.allowlist {
+        Some(Allowlist::Anonymous(_)) => {
+            let verification_key =
+                read_file(&config.artifact("circom/anonymous_verification_key.json"))?;
+            check_verification_key(
+                &String::from_utf8_lossy(&verification_key),
+                ANONYMOUS_PUBLIC_INPUTS,
+            )?;
+            Some(AnonymousZkInfo {
+                verification_key,
+                vote_0001_zkey: read_file(&config.artifact("circom/anonymous_vote_0001.zkey"))?,
+            })
+        }
+        _ => None,
+    };
 
     let zk = ZkInfo {
         verification_key: verification_key,
         vote_is_valid_0001_zkey: vote_is_valid_0001_zkey,
-        generate_witness: generate_witness,
+        anonymous,
     };
 
-    let zk_data = bincode::serialize(&zk).unwrap();
+    let zk_data = bincode::serialize(&zk)?;
 
-    let res = ar.deploy_zksnark(&contract_id, zk_data).await?;
+    let res = store.deploy_zksnark(&contract_id, zk_data).await?;
     let tx_id = res.0;
 
     println!("ZKSnark: Arweave Tx ID: {} ", tx_id);
 
     // we wait till mined (main txn for now)
-    let _mined_res = ar.wait_till_mined(&tx_id).await.unwrap();
+    store.wait_till_mined(&tx_id).await?;
 
     println!(
         "ZKSnark: ZKSnark initialized\n Arweave Tx ID: {} \n For Contract ID: {}",
@@ -171,24 +500,36 @@ async fn init_zk(id: &String) -> Result<(), Box<dyn std::error::Error>> {
     );
     Ok(())
 }
-async fn init_state(cid: &String) -> Result<(), Box<dyn std::error::Error>> {
-    let contract_json = compile().unwrap();
+async fn init_state<C: HarpocratesContract + DeserializeOwned>(
+    config: &Config,
+    store: &dyn ContractStore,
+    cid: &String,
+) -> Result<(), HarpocratesError> {
+    let contract_id = cid.clone();
 
-    let (pk, _) = get_main_keys();
+    let source_tx = fetch_source_tx(store, &contract_id).await?;
+    let source: ContractSource<C> = source_tx.contract()?;
 
-    let contract_id = cid.clone();
+    // the tally ignores a state initialized by anyone else
+    let address = store.eth_address().await?;
+    if !address.eq_ignore_ascii_case(&source_tx.meta.eth_address) {
+        return Err(HarpocratesError::Contract(format!(
+            "only the deployer {} can initialize the state, not {}",
+            source_tx.meta.eth_address, address
+        )));
+    }
 
-    let ar = crate::arweave::Ar::new("./arweave-keyfile.json".to_string()).await;
+    let (pk, _) = get_main_keys(config, &contract_id, &source.contract)?;
 
     // get the init state, all vectors of 0
-    let init_state = get_initial_state(&contract_json, &pk).unwrap();
+    let init_state = get_initial_state(&source.contract, &source.app, &pk)?;
 
-    let r = ar.initialize_state(&contract_id, init_state).await.unwrap();
+    let r = store.initialize_state(&contract_id, init_state).await?;
 
     println!("Init: Arweave Tx ID: {} ", r.0);
 
     // we wait till mined (main txn for now)
-    let _mined_res = ar.wait_till_mined(&r.0).await.unwrap();
+    store.wait_till_mined(&r.0).await?;
 
     println!(
         "Init: State for Contract ID {} has been initialized ",
@@ -196,147 +537,412 @@ async fn init_state(cid: &String) -> Result<(), Box<dyn std::error::Error>> {
     );
     Ok(())
 }
-async fn fetch_latest(cid: &String) -> Result<(), Box<dyn std::error::Error>> {
+async fn fetch_latest(
+    config: &Config,
+    store: &dyn ContractStore,
+    cid: &String,
+) -> Result<(), HarpocratesError> {
     let contract_id = cid.clone();
 
-    let ar = crate::arweave::Ar::new("./arweave-keyfile.json".to_string()).await;
-    ar.fetch_latest_state(contract_id.to_string())
-        .await
-        .unwrap();
-    println!("Successfully fetched transactions, it is located at .cache/transactions.json");
+    let (source, interactions) = store.fetch_latest_state(contract_id.to_string()).await?;
+
+    // anyone can publish a source under this ID, only the deployer's reproduces it
+    let source = verified_sources(&contract_id, source)?;
+
+    let (public_key, zk_params) = match source.first() {
+        Some(source) => (
+            fetch_admin_pk(store, source).await.ok(),
+            store.fetch_zk(source).await.ok().map(|zk| zk.meta.id),
+        ),
+        None => (None, None),
+    };
+
+    create_cache_dir(config)?;
+
+    write_to_file(
+        config,
+        "transactions.json".to_string(),
+        serde_json::to_string(&Transactions {
+            interactions,
+            source,
+            public_key,
+            zk_params,
+        })?,
+    )?;
+    println!(
+        "Successfully fetched transactions, it is located at {}",
+        config.cache("transactions.json").display()
+    );
     Ok(())
 }
-async fn fetch_zk(cid: &String) -> Result<(), Box<dyn std::error::Error>> {
-    let contract_id = cid.clone();
+async fn fetch_zk(
+    config: &Config,
+    store: &dyn ContractStore,
+    cid: &String,
+) -> Result<(), HarpocratesError> {
+    // only the params signed by the deployer, anyone can tag params with the contract ID
+    let source = fetch_source_tx(store, cid).await?;
+    let zk_data = store.fetch_zk(&source).await?.data;
+
+    create_cache_dir(config)?;
+
+    let zk_path = config.cache("zksnark.bin");
+    fs::write(&zk_path, &zk_data).map_err(HarpocratesError::cache(&zk_path))?;
+
+    let all: ZkInfo = bincode::deserialize(&zk_data)?;
 
-    let ar = crate::arweave::Ar::new("./arweave-keyfile.json".to_string()).await;
-    let zk_data = ar.fetch_zk(contract_id.to_string()).await.unwrap();
-
-    let all: ZkInfo = bincode::deserialize(&zk_data).unwrap();
-
-    let mut file = File::create(format!("./.cache/{}", "generate_witness".to_string()))?;
-    let metadata = file.metadata()?;
-    let mut permissions = metadata.permissions();
-    permissions.set_mode(0o777);
-    file.write_all(&all.generate_witness)?;
-    let mut file = File::create(format!(
-        "./.cache/{}",
-        "vote_is_valid_0001.zkey".to_string()
-    ))?;
-    file.write_all(&all.vote_is_valid_0001_zkey)?;
-    let mut file = File::create(format!("./.cache/{}", "verification_key.json".to_string()))?;
-    file.write_all(&all.verification_key)?;
-
-    println!("Successfully fetched Zk information, it is located at .cache/zksnark.bin");
+    let mut files = vec![
+        ("vote_is_valid_0001.zkey", &all.vote_is_valid_0001_zkey),
+        ("verification_key.json", &all.verification_key),
+    ];
+    if let Some(anonymous) = &all.anonymous {
+        files.push(("anonymous_vote_0001.zkey", &anonymous.vote_0001_zkey));
+        files.push((
+            "anonymous_verification_key.json",
+            &anonymous.verification_key,
+        ));
+    }
+    for (name, data) in files {
+        let path = config.cache(name);
+        fs::write(&path, data).map_err(HarpocratesError::cache(&path))?;
+    }
+
+    println!(
+        "Successfully fetched Zk information, it is located at {}",
+        zk_path.display()
+    );
     Ok(())
 }
-async fn compute_latest() -> Result<(), Box<dyn std::error::Error>> {
-    // we get the contract from source
+async fn fetch_pk(
+    config: &Config,
+    store: &dyn ContractStore,
+    cid: &String,
+) -> Result<(), HarpocratesError> {
+    let source = fetch_source_tx(store, cid).await?;
+    let pk = fetch_admin_pk(store, &source).await?;
 
-    let (pk, sk) = get_main_keys();
-
-    let txs_string = std::fs::read_to_string("./.cache/transactions.json")
-        .expect("Should have been able to read the file");
-
-    let txns: Transactions = serde_json::from_str(&txs_string).unwrap();
-
-    let source = txns.source[0].clone();
-
-    let app: Application = serde_json::from_value(source["data"].clone()).unwrap();
-
-    let intxs = txns.interactions;
-
-    // we get the init state first
-    let init = &intxs[0];
-    let t_s = serde_json::to_string(&init["data"]).unwrap();
-    let mut curr_calc: Ciphertext = serde_json::from_str(&t_s).unwrap();
-
-    // we go through all transactions and run them one by one through the compiled contract
-    for intx in intxs {
-        // we verify the zkp
-
-        // first deserialize the inputs
-        // need to do this because of some weird bug with serde
-
-        /*
-        thread 'main' panicked at 'called `Result::unwrap()` on an
-        `Err` value: Error("invalid type: string \"params\", expected a borrowed string", line: 0, column: 0)', /
-
-        happens when serde_json::from_value(intx["data"].clone()).unwrap();
-        */
-        // todo add verifying eth sig
-        // this bit does the calculations
-        #[allow(unused_assignments)]
-        let mut t_s = String::from("");
-        if !intx["data"].is_array() && intx["data"]["data"].is_string() {
-            t_s = intx["data"]["data"].as_str().unwrap().to_string();
-            let zkp = intx["data"]["zkp"].as_object().unwrap();
-
-            let proof: String = serde_json::from_value(zkp["proof"].clone()).unwrap();
-            let public: String = serde_json::from_value(zkp["public"].clone()).unwrap();
-
-            write_to_file("proof_to_check.json".to_string(), proof)?;
-            write_to_file("public_input_to_check.json".to_string(), public)?;
-
-            println!(
-                "Verifying ZKSnark for {}",
-                intx["id"].clone().as_str().unwrap()
-            );
-            match verify_snark_proof(
-                "./.cache/public_input_to_check.json",
-                "./.cache/proof_to_check.json",
-            ) {
-                Ok(_) => {}
-                Err(_) => {
-                    // if the ZKsnark is not valid, we skip this txn
-
-                    println!(">>>>>>>>>>>>> Warning <<<<<<<<<<<<<");
-                    println!(">>>>>>>>>>>>> Warning <<<<<<<<<<<<<");
-                    println!(">>>>>>>>>>>>> Warning <<<<<<<<<<<<<");
-                    println!(">>>>>>>>>>>>> ZKSnark not valid, skipping this txn <<<<<<<<<<<<<");
-                    println!(">>>>>>>>>>>>> Warning <<<<<<<<<<<<<");
-                    println!(">>>>>>>>>>>>> Warning <<<<<<<<<<<<<");
-                    println!(">>>>>>>>>>>>> Warning <<<<<<<<<<<<<");
-                    continue;
-                }
+    create_cache_dir(config)?;
+    write_to_file(config, "admin_pk.json".to_string(), pk.public_key)?;
+
+    println!(
+        "Successfully fetched the admin public key, it is located at {}",
+        config.cache("admin_pk.json").display()
+    );
+    Ok(())
+}
+// what replaying the interactions of a contract gives, before anything is decrypted
+struct Replay {
+    state: Vec<Ciphertext>,
+    // number of ballots summed in the state
+    counted: usize,
+    // last interaction the state accounts for
+    last: Option<String>,
+    // anything that can't be counted, along with the reason
+    dropped: Vec<(String, String)>,
+    duplicates: Vec<Duplicate>,
+    // the close interaction, if the deployer published one
+    closed: Option<String>,
+}
+
+fn read_transactions(config: &Config) -> Result<Transactions, HarpocratesError> {
+    let txs_path = config.cache("transactions.json");
+    let txs_string = fs::read_to_string(&txs_path).map_err(HarpocratesError::cache(&txs_path))?;
+    let txns: Transactions = serde_json::from_str(&txs_string)?;
+    if txns.source.is_empty() {
+        return Err(HarpocratesError::Contract(format!(
+            "no source in {}",
+            txs_path.display()
+        )));
+    }
+    Ok(txns)
+}
+
+// runs the interactions one by one through the compiled contract, up to `until` included.
+// Only the public key is needed, so anyone can replay a contract
+fn replay<C: HarpocratesContract>(
+    config: &Config,
+    txns: Transactions,
+    source: &ContractSource<C>,
+    pk: &PublicKey,
+    policy: DuplicatePolicy,
+    identity: Identity,
+    until: Option<&str>,
+) -> Result<Replay, HarpocratesError> {
+    let contract = &source.contract;
+    let app = &source.app;
+    let deployer = &txns.source[0].meta.eth_address;
+    let mut members = source.allowlist.clone().map(Members::new);
+    let window = source.window;
+
+    // fetched along with the zk params
+    let vk_path = config.cache("verification_key.json");
+    let verification_key =
+        fs::read_to_string(&vk_path).map_err(HarpocratesError::cache(&vk_path))?;
+    // only needed if there are anonymous votes
+    let anonymous_verification_key =
+        fs::read_to_string(config.cache("anonymous_verification_key.json")).ok();
+
+    let mut dropped: Vec<(String, String)> = vec![];
+    let mut state: Option<Vec<Ciphertext>> = None;
+    let mut ballots = Ballots::new(policy, identity);
+    let mut closed: Option<String> = None;
+    let mut counted = 0;
+    let mut last: Option<String> = None;
+
+    for intx in txns.interactions {
+        if last.is_some() && last.as_deref() == until {
+            break;
+        }
+        last = Some(intx.meta.id.clone());
+
+        // it has to be signed by the address in its tags, anonymous votes have neither.
+        // The voters of an aggregate sign it on top of whoever publishes it
+        if !matches!(intx.interaction, Interaction::AnonymousVote(_)) {
+            if let Err(e) = intx.meta.verify_eth_signature() {
+                dropped.push((intx.meta.id, e.to_string()));
+                continue;
+            }
+        }
+
+        // once closed, the poll only takes decryption
+        if let Some(close) = &closed {
+            if !matches!(intx.interaction, Interaction::Decrypt(_)) {
+                dropped.push((
+                    intx.meta.id,
+                    format!("sent after the poll was closed by {}", close),
+                ));
+                continue;
+            }
+        }
+
+        // votes count if they were mined while the poll was open, whatever their Unix-Time tag says
+        if matches!(
+            intx.interaction,
+            Interaction::Vote(_) | Interaction::AnonymousVote(_) | Interaction::AggregateVote(_)
+        ) {
+            if let Err(e) = window.check(intx.meta.block.as_ref()) {
+                dropped.push((intx.meta.id, e.to_string()));
+                continue;
+            }
+            // and if they were proven with the params of the deployer, not of another ceremony
+            let proven_with = intx.meta.input.validity_proof.as_deref();
+            let unproven = match txns.zk_params.as_deref() {
+                None => Some("the deployer published no zk params".to_string()),
+                Some(zk_params) if proven_with != Some(zk_params) => Some(format!(
+                    "proven with the zk params {} instead of {}",
+                    proven_with.unwrap_or("none"),
+                    zk_params
+                )),
+                Some(_) => None,
             };
+            if let Some(reason) = unproven {
+                dropped.push((intx.meta.id, reason));
+                continue;
+            }
+        }
 
-            println!("ZKProof verified {}", intx["id"].clone().as_str().unwrap());
+        let by_deployer = intx.meta.eth_address.eq_ignore_ascii_case(deployer);
+
+        // a malformed or unproven input is skipped, it should not stop the tally
+        let input = match intx.interaction {
+            // anyone else could start the tally from encrypted votes of their own
+            Interaction::InitState(_) if !by_deployer => Err(HarpocratesError::Contract(format!(
+                "only the deployer {} can initialize the state",
+                deployer
+            ))),
+            Interaction::InitState(init) if init.len() != contract.width() => {
+                Err(HarpocratesError::Contract(format!(
+                    "the initial state has {} values instead of {}",
+                    init.len(),
+                    contract.width()
+                )))
+            }
+            Interaction::InitState(init) if state.is_none() => {
+                state = Some(init);
+                continue;
+            }
+            Interaction::InitState(_) => Err(HarpocratesError::Contract(
+                "the state is already initialized".to_string(),
+            )),
+            // only listed signers can vote
+            Interaction::Vote(vote) => match &members {
+                Some(members) => members.check(&intx.meta.eth_address, vote.membership.as_ref()),
+                None => Ok(()),
+            }
+            .and_then(|_| {
+                read_vote(
+                    &verification_key,
+                    txns.public_key.as_ref(),
+                    &intx.meta,
+                    &vote,
+                )
+            })
+            .map(|input| (input, Voters::Sender)),
+            Interaction::AggregateVote(aggregate) => {
+                read_aggregate(&verification_key, members.as_ref(), &intx.meta, &aggregate)
+                    .map(|(input, voters)| (input, Voters::Aggregate(voters)))
+            }
+            Interaction::AnonymousVote(vote) => read_anonymous_vote(
+                anonymous_verification_key.as_deref(),
+                txns.public_key.as_ref(),
+                members.as_mut(),
+                &intx.meta,
+                &vote,
+            )
+            .map(|(input, nullifier)| (input, Voters::Nullifier(nullifier))),
+            // only whoever deployed the contract manages who votes on it
+            Interaction::Allowlist(update) => match &mut members {
+                Some(members) if by_deployer => {
+                    members.apply(&update);
+                    continue;
+                }
+                Some(_) => Err(HarpocratesError::Contract(format!(
+                    "only the deployer {} can update the allowlist",
+                    deployer
+                ))),
+                None => Err(HarpocratesError::Contract(
+                    "the contract has no allowlist".to_string(),
+                )),
+            },
+            Interaction::Close if by_deployer => {
+                closed = Some(intx.meta.id);
+                continue;
+            }
+            Interaction::Close => Err(HarpocratesError::Contract(format!(
+                "only the deployer {} can close the poll",
+                deployer
+            ))),
+            // results don't change the state, see check-result
+            Interaction::Decrypt(_) if by_deployer => continue,
+            Interaction::Decrypt(_) => Err(HarpocratesError::Contract(format!(
+                "only the deployer {} can publish the result",
+                deployer
+            ))),
+            Interaction::Invalid(reason) => Err(HarpocratesError::Serialization(reason)),
+        };
+        let (input, voters) = match input {
+            Ok(input) => input,
+            Err(e) => {
+                dropped.push((intx.meta.id, e.to_string()));
+                continue;
+            }
+        };
 
-            // we take out the proof.json and the input.json, save it and run the verify proof on them
-        } else {
-            t_s = serde_json::to_string(&intx["data"]).unwrap();
+        // an input that does not cover every option would corrupt the tally
+        if input.len() != contract.width() {
+            dropped.push((
+                intx.meta.id,
+                format!(
+                    "input has {} values instead of {}",
+                    input.len(),
+                    contract.width()
+                ),
+            ));
+            continue;
         }
 
-        let input: Ciphertext = serde_json::from_str(&t_s).unwrap();
+        let curr_calc = match state.take() {
+            Some(curr_calc) => curr_calc,
+            None => {
+                dropped.push((
+                    intx.meta.id,
+                    "sent before the state was initialized".to_string(),
+                ));
+                continue;
+            }
+        };
+
+        // only one ballot per identity is counted, anonymous voters are known by their nullifier
+        let (decision, weight) = match voters {
+            Voters::Sender => (ballots.record(&intx.meta, &input), 1),
+            Voters::Nullifier(nullifier) => {
+                (ballots.record_for(nullifier, &intx.meta.id, &input), 1)
+            }
+            Voters::Aggregate(voters) => (
+                ballots.record_aggregate(&voters, &intx.meta.id),
+                voters.len(),
+            ),
+        };
+
+        // the state is only updated once the ballot went through, a failing run drops it
+        let next = match decision {
+            Decision::Add => calculate(contract, app, pk, curr_calc.clone(), input)
+                .map(|next| (next, counted + weight)),
+            Decision::Replace(previous) => revert(contract, app, pk, curr_calc.clone(), previous)
+                .and_then(|next| calculate(contract, app, pk, next, input))
+                .map(|next| (next, counted)),
+            Decision::Remove(previous) => revert(contract, app, pk, curr_calc.clone(), previous)
+                .map(|next| (next, counted - 1)),
+            Decision::Skip => {
+                state = Some(curr_calc);
+                continue;
+            }
+        };
+        match next {
+            Ok((next, now_counted)) => {
+                state = Some(next);
+                counted = now_counted;
+            }
+            Err(e) => {
+                ballots.revoke();
+                dropped.push((intx.meta.id, format!("cannot be counted: {}", e)));
+                state = Some(curr_calc);
+            }
+        }
+    }
 
-        let args = vec![curr_calc, input.clone()];
-        curr_calc = calculate(&app, &pk, args).unwrap();
+    if until.is_some() && last.as_deref() != until {
+        return Err(HarpocratesError::Contract(format!(
+            "interaction {} not found",
+            until.unwrap_or_default()
+        )));
     }
 
-    let decrypted = decrypt(&app, curr_calc, &sk).unwrap();
+    let state = state.ok_or_else(|| {
+        HarpocratesError::Contract("the contract state has not been initialized".to_string())
+    })?;
+
+    Ok(Replay {
+        state,
+        counted,
+        last,
+        dropped,
+        duplicates: ballots.duplicates,
+        closed,
+    })
+}
 
-    // then we decrypt the output calculation
-    println!("Compute Latest: current poll is {:?}", decrypted);
+async fn compute_latest<C: HarpocratesContract + DeserializeOwned>(
+    config: &Config,
+    policy: DuplicatePolicy,
+    identity: Identity,
+) -> Result<(), HarpocratesError> {
+    let txns = read_transactions(config)?;
 
-    let mut table = Table::new();
-    table.add_row(row!["A", "B", "C", "D", "E", "F", "G", "H", "I", "J"]);
-    let rows = [
-        decrypted[0],
-        decrypted[1],
-        decrypted[2],
-        decrypted[3],
-        decrypted[4],
-        decrypted[5],
-        decrypted[6],
-        decrypted[7],
-        decrypted[8],
-        decrypted[9],
-    ];
+    // we get the contract from source
+    let source: ContractSource<C> = txns.source[0].contract()?;
+    let contract = &source.contract;
+
+    let (pk, sk) = get_main_keys(config, &txns.source[0].meta.contract, contract)?;
 
-    let normal_rows: [Cell; 10] = rows.map(|x| Cell::new(&x.to_string()));
+    let replay = replay(config, txns, &source, &pk, policy, identity, None)?;
+
+    let decrypted = decrypt(contract, &source.app, &replay.state, &sk)?;
+
+    // then we decrypt the output calculation
+    let summary = contract.summary(&decrypted);
+    println!("Compute Latest: current state is {:?}", summary);
 
-    table.add_row(Row::new(normal_rows.to_vec()));
+    let mut table = Table::new();
+    table.add_row(Row::new(
+        summary
+            .iter()
+            .map(|(header, _)| Cell::new(header))
+            .collect(),
+    ));
+    table.add_row(Row::new(
+        summary.iter().map(|(_, value)| Cell::new(value)).collect(),
+    ));
 
     // we clear the screen
     clear_screen();
@@ -353,89 +959,843 @@ async fn compute_latest() -> Result<(), Box<dyn std::error::Error>> {
     println!("{}", ">>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>".green());
     println!("{}", ">>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>".green());
 
+    if let Some(close) = &replay.closed {
+        println!("The poll was closed by {}, this is its final result", close);
+    }
+
+    if !replay.dropped.is_empty() {
+        println!("{}", "Dropped interactions".yellow());
+        let mut table = Table::new();
+        table.add_row(Row::new(vec![
+            Cell::new("Transaction"),
+            Cell::new("Reason"),
+        ]));
+        for (id, reason) in &replay.dropped {
+            table.add_row(Row::new(vec![Cell::new(id), Cell::new(reason)]));
+        }
+        table.printstd();
+    }
+
+    if !replay.duplicates.is_empty() {
+        println!(
+            "{}",
+            format!("Duplicate votes ({:?} counts)", policy).yellow()
+        );
+        let mut table = Table::new();
+        table.add_row(Row::new(vec![
+            Cell::new("Identity"),
+            Cell::new("Transaction"),
+            Cell::new("Outcome"),
+        ]));
+        for duplicate in &replay.duplicates {
+            table.add_row(Row::new(vec![
+                Cell::new(&duplicate.identity),
+                Cell::new(&duplicate.tx_id),
+                Cell::new(&duplicate.outcome),
+            ]));
+        }
+        table.printstd();
+    }
+
     Ok(())
 }
 
+// who a ballot is counted for
+enum Voters {
+    // the identity of the transaction, see `--identity`
+    Sender,
+    Nullifier(String),
+    // the lowercased addresses of the voters of an aggregate
+    Aggregate(Vec<String>),
+}
+
 fn clear_screen() {
     print!("\x1B[2J\x1B[1;1H");
 }
 
-async fn vote(id: &String, index: &usize) -> Result<(), Box<dyn std::error::Error>> {
-    if index > &9 && index < &0 {
-        println!("Invalid choice, only from 0-9");
-        return Ok(());
+// the encrypted ballot of a vote, once its ZK proof is verified
+fn read_vote(
+    verification_key: &str,
+    public_key: Option<&PublicKeyTx>,
+    meta: &TxMeta,
+    vote: &VoteData,
+) -> Result<Vec<Ciphertext>, HarpocratesError> {
+    // the proof has to be about this very ciphertext, encrypted to the published key,
+    // and made for the contract and the address in the tags
+    let binding = vote_binding(public_key, vote)?;
+    let statement = VoteStatement::new(binding, &meta.contract, &meta.eth_address);
+
+    // we take out the proof.json and the public.json and verify them against the verification key
+    println!("Verifying ZKSnark for {}", meta.id);
+    match verify_vote(
+        verification_key,
+        &vote.zkp.proof,
+        &vote.zkp.public,
+        &statement,
+    )? {
+        Verification::Valid => println!("ZKProof verified {}", meta.id),
+        Verification::Invalid(reason) => {
+            return Err(HarpocratesError::Proof(format!(
+                "ZKSnark not valid, {}",
+                reason
+            )))
+        }
     }
 
-    let contract_id = id.clone();
+    Ok(serde_json::from_str(&vote.data)?)
+}
 
-    let contract_json = compile().unwrap();
+// the encrypted ballot of an anonymous vote and the nullifier of its voter, once its ZK proof is verified
+fn read_anonymous_vote(
+    verification_key: Option<&str>,
+    public_key: Option<&PublicKeyTx>,
+    members: Option<&mut Members>,
+    meta: &TxMeta,
+    vote: &VoteData,
+) -> Result<(Vec<Ciphertext>, String), HarpocratesError> {
+    let verification_key = verification_key.ok_or_else(|| {
+        HarpocratesError::Proof(
+            "anonymous_verification_key.json is not in the cache, run fetch-zk".to_string(),
+        )
+    })?;
+    let members = members.ok_or_else(|| {
+        HarpocratesError::Contract("the contract doesn't take anonymous votes".to_string())
+    })?;
+
+    // the proof has to be about this very ciphertext and contract, and made by a member of
+    // the allowlist as it is at this point of the replay
+    let statement = AnonymousStatement::new(vote_binding(public_key, vote)?, &meta.contract);
+    let root = members.group()?.root();
+
+    println!("Verifying anonymous ZKSnark for {}", meta.id);
+    match verify_anonymous_vote(
+        verification_key,
+        &vote.zkp.proof,
+        &vote.zkp.public,
+        &statement,
+        &root,
+    )? {
+        Verification::Valid => println!("ZKProof verified {}", meta.id),
+        Verification::Invalid(reason) => {
+            return Err(HarpocratesError::Proof(format!(
+                "ZKSnark not valid, {}",
+                reason
+            )))
+        }
+    }
 
-    let app: Application = serde_json::from_str(&contract_json).unwrap();
+    Ok((
+        serde_json::from_str(&vote.data)?,
+        anonymous_nullifier(&vote.zkp.public)?,
+    ))
+}
 
-    let runtime = Runtime::new(app.params()).unwrap();
+// the sum of an aggregate and its voters, once every voter's signature and proof are verified.
+// The sum itself can't be checked, the voters vouch for it by signing it
+fn read_aggregate(
+    verification_key: &str,
+    members: Option<&Members>,
+    meta: &TxMeta,
+    aggregate: &AggregateVote,
+) -> Result<(Vec<Ciphertext>, Vec<String>), HarpocratesError> {
+    if aggregate.contract != meta.contract {
+        return Err(HarpocratesError::Contract(format!(
+            "the aggregate was made for contract {}",
+            aggregate.contract
+        )));
+    }
+    // a single ballot would be published, and counted, as if it was a sum
+    if aggregate.ballots.len() < 2 {
+        return Err(HarpocratesError::Contract(format!(
+            "the aggregate has {} ballot(s), at least 2 are needed",
+            aggregate.ballots.len()
+        )));
+    }
+    aggregate.verify_signatures()?;
 
-    let ar = crate::arweave::Ar::new("./arweave-keyfile.json".to_string()).await;
+    println!("Verifying the aggregated ZKSnarks of {}", meta.id);
+    for ballot in &aggregate.ballots {
+        if let Some(members) = members {
+            members.check(&ballot.eth_address, ballot.membership.as_ref())?;
+        }
 
-    let (pk, _) = get_main_keys();
+        // proofs are bound to ballots we don't see, the voters signed their bindings
+        let binding = BallotBinding::parse(&ballot.binding)?;
+        let statement = VoteStatement::new(binding, &meta.contract, &ballot.eth_address);
+        match verify_vote(
+            verification_key,
+            &ballot.zkp.proof,
+            &ballot.zkp.public,
+            &statement,
+        )? {
+            Verification::Valid => {}
+            Verification::Invalid(reason) => {
+                return Err(HarpocratesError::Proof(format!(
+                    "ZKSnark of {} not valid, {}",
+                    ballot.eth_address, reason
+                )))
+            }
+        }
+    }
+    println!("ZKProofs verified {}", meta.id);
+
+    Ok((
+        serde_json::from_str(&aggregate.data)?,
+        aggregate
+            .ballots
+            .iter()
+            .map(|ballot| Identity::address(&ballot.eth_address))
+            .collect(),
+    ))
+}
 
-    // we need at least 1 other person to vote with us to somewhat obfuscate our vote. Hence, we will store the vote in the cache if
-    // first to vote, otherwise we add up a vote with another person and publish it. (we can also do a peer to peer check to ensure it will vote as we want it to).
-    // both parties will need to create a zkproof saying this was their vote (they participated in it).
-    // this is mitigated if we use MKFHE - where everyone can encrypt their vote, publish it and have it all counted + decrypted at the end.
+// what the proof of a vote is bound to: its ciphertext and the key it is encrypted to
+fn vote_binding(
+    public_key: Option<&PublicKeyTx>,
+    vote: &VoteData,
+) -> Result<BallotBinding, HarpocratesError> {
+    let public_key = public_key.ok_or_else(|| {
+        HarpocratesError::Key("the contract has no valid published public key".to_string())
+    })?;
+    Ok(BallotBinding::new(
+        public_key.public_key.as_bytes(),
+        vote.data.as_bytes(),
+    ))
+}
 
-    // this is where MKFHE would come in, some schemes (bfv etc show research) can show a validity proof of the encryption (as in, I can show you my vote is valid)
-    let mut og_vote = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
-    og_vote[*index] = 1;
+async fn vote(
+    config: &Config,
+    store: &dyn ContractStore,
+    id: &String,
+    index: &usize,
+    membership: Option<MerkleProof>,
+    queue: Option<&Path>,
+) -> Result<(), HarpocratesError> {
+    let contract_id = id.clone();
 
-    // let's create a proof that our vote is valid
+    // the number of options is the one the contract was deployed with
+    let source_tx = fetch_source_tx(store, &contract_id).await?;
+    let source: ContractSource<VoteContract> = source_tx.contract()?;
+    let contract = &source.contract;
 
-    // we create a file called input.json that will be the input to our circuit (our vote)
-    write_to_file(
-        "input.json".to_string(),
-        json!({ "vote": og_vote }).to_string(),
-    )?;
+    if *index >= contract.options {
+        println!("Invalid choice, only from 0-{}", contract.options - 1);
+        return Ok(());
+    }
 
-    // we now create a witness
-    generate_witness()?;
+    // the tally only has the root to check us against
+    if matches!(source.allowlist, Some(Allowlist::MerkleRoot(_))) && membership.is_none() {
+        return Err(HarpocratesError::Contract(format!(
+            "contract {} only counts votes from its allowlist, vote with --membership-proof",
+            contract_id
+        )));
+    }
 
-    // we now generate the proof
-    generate_proof()?;
+    // the voters of an aggregate sign it, so they can't be anonymous
+    if matches!(source.allowlist, Some(Allowlist::Anonymous(_))) && queue.is_some() {
+        return Err(HarpocratesError::Contract(format!(
+            "contract {} takes anonymous votes, they can't be aggregated",
+            contract_id
+        )));
+    }
 
-    let proof_string = std::fs::read_to_string("./.cache/proof.json")?;
+    let runtime = Runtime::new(source.app.params())?;
 
-    let public_string = std::fs::read_to_string("./.cache/public.json")?;
+    // the ballot is encrypted to the admin, no secret key is needed to vote
+    let pk_tx = fetch_admin_pk(store, &source_tx).await?;
+    let pk = pk_tx.key()?;
 
-    let zkp = ZKProof {
-        proof: proof_string,
-        public: public_string,
-    };
+    // we need at least 1 other person to vote with us to somewhat obfuscate our vote. Hence, with --queue we stage
+    // our vote, and `aggregate` adds it up with the others' and publishes the sum once everyone signed it.
+    // every voter creates a zkproof saying this was their vote (they participated in it).
+    // this is mitigated if we use MKFHE - where everyone can encrypt their vote, publish it and have it all counted + decrypted at the end.
 
-    let vote = og_vote.map(|x| Signed::from(x));
+    // this is where MKFHE would come in, some schemes (bfv etc show research) can show a validity proof of the encryption (as in, I can show you my vote is valid)
+    let mut og_vote = contract.ballot(*index)?;
+
+    // let's create a proof that our vote is valid
+    // the circuit has a fixed number of inputs, so we pad our ballot with 0s
+    og_vote.resize(VOTE_CIRCUIT_OPTIONS, 0);
 
     // we encrypt it
-    let vote_enc = runtime.encrypt(vote, &pk).unwrap();
+    let vote_enc = contract.encode_input(&runtime, &pk, *index)?;
+
+    let v_d = serde_json::to_string(&vote_enc)?;
+
+    // we compute the witness of our vote and prove it, the proof is bound to our ciphertext
+    // and to this contract
+    let binding = BallotBinding::new(pk_tx.public_key.as_bytes(), v_d.as_bytes());
+    let (proof_string, public_string) = match &source.allowlist {
+        // and shows we are on the allowlist without saying who we are
+        Some(allowlist @ Allowlist::Anonymous(_)) => {
+            prove_anonymously(
+                config,
+                store,
+                &source_tx,
+                allowlist.clone(),
+                &og_vote,
+                binding,
+            )
+            .await?
+        }
+        // and to the address signing the vote
+        _ => {
+            // the circuit comes with the artifacts, the proving key with the zk params of the contract
+            let prover = VoteProver::new(
+                &config.artifact("circom/vote_is_valid_js/vote_is_valid.wasm"),
+                &config.artifact("circom/vote_is_valid.r1cs"),
+                &config.cache("vote_is_valid_0001.zkey"),
+            )?;
+            let statement = VoteStatement::new(binding, &contract_id, &store.eth_address().await?);
+            prover.prove(&og_vote, &statement)?
+        }
+    };
 
-    let v_d = serde_json::to_string(&vote_enc).unwrap();
+    let zkp = ZKProof {
+        proof: proof_string,
+        public: public_string,
+    };
 
     let vote_data = VoteData {
         data: v_d,
         zkp: zkp,
+        membership,
     };
 
-    let vote_data_string = serde_json::to_string(&vote_data).unwrap();
+    if let Some(queue) = queue {
+        let ballot = StagedBallot {
+            eth_address: store.eth_address().await?,
+            vote: vote_data,
+        };
+        BallotQueue::new(queue.to_path_buf()).stage(&contract_id, &ballot)?;
+        println!(
+            "Vote: Your vote is staged in {}, run aggregate once the others staged theirs",
+            queue.display()
+        );
+        return Ok(());
+    }
+
+    let vote_data_string = serde_json::to_string(&vote_data)?;
 
     // wait for it to get mined
-    let res = ar.vote(&contract_id, vote_data_string).await.unwrap();
+    let res = match source.allowlist {
+        Some(Allowlist::Anonymous(_)) => {
+            store
+                .vote_anonymously(&contract_id, vote_data_string)
+                .await?
+        }
+        _ => store.vote(&contract_id, vote_data_string).await?,
+    };
     println!("Vote: Your vote is being sent ArID {} ", res.0);
 
     // we wait till mined (main txn for now)
-    let _mined_res = ar.wait_till_mined(&res.0).await.unwrap();
+    store.wait_till_mined(&res.0).await?;
 
     println!("Vote: Your vote has been mined for {} ", contract_id);
     Ok(())
 }
 
-pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
+// sums the staged ballots and signs the sum, then publishes it if every voter signed it
+async fn aggregate(
+    config: &Config,
+    store: &dyn ContractStore,
+    contract_id: &String,
+    queue: &BallotQueue,
+) -> Result<(), HarpocratesError> {
+    let source_tx = fetch_source_tx(store, contract_id).await?;
+    let source: ContractSource<VoteContract> = source_tx.contract()?;
+
+    // a ballot on its own would be decrypted on its own
+    let staged = queue.staged(contract_id)?;
+    if staged.len() < 2 {
+        return Err(HarpocratesError::Contract(format!(
+            "{} ballot(s) staged for contract {}, at least 2 are needed",
+            staged.len(),
+            contract_id
+        )));
+    }
+
+    let pk_tx = fetch_admin_pk(store, &source_tx).await?;
+    let pk = pk_tx.key()?;
+
+    // we only sign ballots the tally will count, and whose binding we computed ourselves
+    let vk_path = config.cache("verification_key.json");
+    let verification_key =
+        fs::read_to_string(&vk_path).map_err(HarpocratesError::cache(&vk_path))?;
+    let mut ballots = vec![];
+    let mut inputs = vec![];
+    for ballot in &staged {
+        let binding = vote_binding(Some(&pk_tx), &ballot.vote)?;
+        let statement = VoteStatement::new(binding, contract_id, &ballot.eth_address);
+        if let Verification::Invalid(reason) = verify_vote(
+            &verification_key,
+            &ballot.vote.zkp.proof,
+            &ballot.vote.zkp.public,
+            &statement,
+        )? {
+            return Err(HarpocratesError::Proof(format!(
+                "the ballot staged by {} is not valid, {}",
+                ballot.eth_address, reason
+            )));
+        }
+
+        let input: Vec<Ciphertext> = serde_json::from_str(&ballot.vote.data)?;
+        inputs.push(input);
+        ballots.push(AggregatedBallot {
+            // as the voter signs it
+            eth_address: ballot.eth_address.clone(),
+            binding: binding.to_string(),
+            zkp: ballot.vote.zkp.clone(),
+            membership: ballot.vote.membership.clone(),
+            eth_signature: String::new(),
+        });
+    }
+
+    // everyone sums the ballots in the same order, so they all sign the same aggregate
+    let mut inputs = inputs.into_iter();
+    let first = inputs.next().unwrap_or_default();
+    let sum = inputs.try_fold(first, |sum, input| {
+        calculate(&source.contract, &source.app, &pk, sum, input)
+    })?;
+    let mut aggregate = AggregateVote {
+        contract: contract_id.clone(),
+        data: serde_json::to_string(&sum)?,
+        ballots,
+    };
+    let message = aggregate.message();
+
+    let address = store.eth_address().await?;
+    let signatures = match aggregate
+        .ballots
+        .iter()
+        .any(|b| b.eth_address.eq_ignore_ascii_case(&address))
+    {
+        true => {
+            let (_, signature) = store.eth_sign(&message).await?;
+            queue.sign(contract_id, &message, &address, &signature)?
+        }
+        false => queue.signatures(contract_id, &message)?,
+    };
+
+    let missing: Vec<&str> = aggregate
+        .ballots
+        .iter()
+        .filter(|b| !signatures.contains_key(&b.eth_address.to_lowercase()))
+        .map(|b| b.eth_address.as_str())
+        .collect();
+    if !missing.is_empty() {
+        println!(
+            "Aggregate: {} ballots summed, waiting for {} to run aggregate",
+            aggregate.ballots.len(),
+            missing.join(", ")
+        );
+        return Ok(());
+    }
+
+    for ballot in &mut aggregate.ballots {
+        ballot.eth_signature = signatures[&ballot.eth_address.to_lowercase()].clone();
+    }
+    aggregate.verify_signatures()?;
+
+    let res = store
+        .vote_aggregate(contract_id, serde_json::to_string(&aggregate)?)
+        .await?;
+    println!(
+        "Aggregate: The sum of {} votes is being sent ArID {} ",
+        aggregate.ballots.len(),
+        res.0
+    );
+
+    store.wait_till_mined(&res.0).await?;
+    queue.clear(contract_id)?;
+
+    println!("Aggregate: The votes have been mined for {} ", contract_id);
+    Ok(())
+}
+
+// proves our vote as one of the members of the anonymous allowlist, with every update of the deployer
+async fn prove_anonymously(
+    config: &Config,
+    store: &dyn ContractStore,
+    source_tx: &SourceTx,
+    allowlist: Allowlist,
+    vote: &[i64],
+    binding: BallotBinding,
+) -> Result<(String, String), HarpocratesError> {
+    let contract_id = &source_tx.meta.contract;
+    let identity = read_identity(config)?;
+
+    let (_, interactions) = store.fetch_latest_state(contract_id.clone()).await?;
+    let mut members = Members::new(allowlist);
+    for intx in &interactions {
+        if let Interaction::Allowlist(update) = &intx.interaction {
+            if intx.meta.verify_eth_signature().is_ok()
+                && intx
+                    .meta
+                    .eth_address
+                    .eq_ignore_ascii_case(&source_tx.meta.eth_address)
+            {
+                members.apply(update);
+            }
+        }
+    }
+
+    let commitment = identity.commitment();
+    let path = members.group()?.path(&commitment).ok_or_else(|| {
+        HarpocratesError::Contract(format!(
+            "our identity {} is not on the allowlist of contract {}",
+            field_to_string(&commitment),
+            contract_id
+        ))
+    })?;
+
+    let prover = VoteProver::new(
+        &config.artifact("circom/anonymous_vote_js/anonymous_vote.wasm"),
+        &config.artifact("circom/anonymous_vote.r1cs"),
+        &config.cache("anonymous_vote_0001.zkey"),
+    )?;
+    let statement = AnonymousStatement::new(binding, contract_id);
+    prover.prove_anonymous(vote, &statement, &identity, &path)
+}
+
+fn create_identity(config: &Config) -> Result<(), HarpocratesError> {
+    // the commitment may already be on allowlists, its secret can't be replaced
+    let profiles = config.profiles();
+    let path = profiles.file(&config.profile, "identity.json")?;
+    if path.exists() {
+        return Err(HarpocratesError::Key(format!(
+            "{} already exists",
+            path.display()
+        )));
+    }
+
+    let identity = VoterIdentity::random();
+    profiles.create(&config.profile)?;
+    write_private(
+        &path,
+        json!({ "secret": identity.secret() })
+            .to_string()
+            .as_bytes(),
+    )?;
+
+    println!(
+        "Identity created, give this commitment to whoever deploys the vote: {}",
+        field_to_string(&identity.commitment())
+    );
+    Ok(())
+}
+
+fn list_keys(config: &Config) -> Result<(), HarpocratesError> {
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![
+        Cell::new("Profile"),
+        Cell::new("Secret key"),
+        Cell::new("Admin of"),
+    ]));
+    for profile in config.profiles().list()? {
+        let name = match profile.name == config.profile {
+            true => format!("{} (current)", profile.name),
+            false => profile.name,
+        };
+        let sk = match profile.sealed {
+            Some(true) => "encrypted",
+            Some(false) => "not encrypted",
+            None => "none",
+        };
+        table.add_row(Row::new(vec![
+            Cell::new(&name),
+            Cell::new(sk),
+            Cell::new(&profile.administers.join("\n")),
+        ]));
+    }
+    table.printstd();
+    Ok(())
+}
+
+fn seal_keyfile(keyfile: &Path, output: &Path) -> Result<(), HarpocratesError> {
+    if Sealed::is_sealed(keyfile) {
+        return Err(HarpocratesError::Key(format!(
+            "{} is already encrypted",
+            keyfile.display()
+        )));
+    }
+    let jwk = Zeroizing::new(read_file(keyfile)?);
+    // arloader would only fail once we publish
+    serde_json::from_slice::<Value>(&jwk).map_err(|e| {
+        HarpocratesError::Key(format!("{} is not a keyfile: {}", keyfile.display(), e))
+    })?;
+
+    let passphrase = passphrase("Passphrase to encrypt the keyfile: ", true)?;
+    Sealed::seal(&jwk, &passphrase)?.write(output)?;
+
+    println!(
+        "Keyfile encrypted in {}, pass it to --keyfile and delete {}",
+        output.display(),
+        keyfile.display()
+    );
+    Ok(())
+}
+
+fn read_identity(config: &Config) -> Result<VoterIdentity, HarpocratesError> {
+    let path = config.profiles().file(&config.profile, "identity.json")?;
+    let raw = fs::read_to_string(&path).map_err(|e| {
+        HarpocratesError::Key(format!(
+            "cannot read {} ({}), run create-identity first",
+            path.display(),
+            e
+        ))
+    })?;
+    let identity: Value = serde_json::from_str(&raw)?;
+    let secret = identity["secret"]
+        .as_str()
+        .ok_or_else(|| HarpocratesError::Key(format!("no secret in {}", path.display())))?;
+    VoterIdentity::from_secret(secret)
+}
+
+fn read_allowlist(
+    file: Option<&PathBuf>,
+    root: Option<&String>,
+    anonymous: Option<&PathBuf>,
+) -> Result<Option<Allowlist>, HarpocratesError> {
+    if let Some(file) = anonymous {
+        let allowlist = Allowlist::Anonymous(read_commitments(file)?);
+        // the tree has a fixed depth, it has to fit everyone
+        Members::new(allowlist.clone()).group()?;
+        return Ok(Some(allowlist));
+    }
+
+    match (file, root) {
+        (Some(file), _) => Ok(Some(Allowlist::Addresses(read_addresses(file)?))),
+        (None, Some(root)) => {
+            if decode_hex(root)?.len() != 32 {
+                return Err(HarpocratesError::Contract(format!(
+                    "{} is not a Merkle root",
+                    root
+                )));
+            }
+            Ok(Some(Allowlist::MerkleRoot(root.to_lowercase())))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+fn allowlist_proof(config: &Config, file: &Path, address: &str) -> Result<(), HarpocratesError> {
+    let addresses = read_addresses(file)?;
+    let proof = merkle_proof(&addresses, address).ok_or_else(|| {
+        HarpocratesError::Contract(format!("{} is not in {}", address, file.display()))
+    })?;
+
+    create_cache_dir(config)?;
+    write_to_file(
+        config,
+        "membership_proof.json".to_string(),
+        serde_json::to_string(&proof)?,
+    )?;
+    println!(
+        "Successfully created the membership proof for root {}, it is located at {}",
+        merkle_root(&addresses)?,
+        config.cache("membership_proof.json").display()
+    );
+    Ok(())
+}
+
+async fn update_allowlist<C: DeserializeOwned>(
+    store: &dyn ContractStore,
+    cid: &String,
+    add: &[String],
+    remove: &[String],
+) -> Result<(), HarpocratesError> {
+    let source_tx = fetch_source_tx(store, cid).await?;
+    let source: ContractSource<C> = source_tx.contract()?;
+
+    // members are identity commitments for anonymous votes, Eth addresses otherwise
+    let member = |member: &String| match source.allowlist {
+        Some(Allowlist::Anonymous(_)) => parse_field(member).map(|c| field_to_string(&c)),
+        Some(_) => normalize(member),
+        None => Err(HarpocratesError::Contract(format!(
+            "contract {} was deployed without an allowlist",
+            cid
+        ))),
+    };
+    let update = AllowlistUpdate {
+        add: add.iter().map(member).collect::<Result<_, _>>()?,
+        remove: remove.iter().map(member).collect::<Result<_, _>>()?,
+    };
+
+    // the tally ignores updates signed by anyone else
+    let address = store.eth_address().await?;
+    if !address.eq_ignore_ascii_case(&source_tx.meta.eth_address) {
+        return Err(HarpocratesError::Contract(format!(
+            "only the deployer {} can update the allowlist, not {}",
+            source_tx.meta.eth_address, address
+        )));
+    }
+
+    let res = store
+        .update_allowlist(cid, serde_json::to_string(&update)?)
+        .await?;
+    println!("Allowlist: Arweave Tx ID: {} ", res.0);
+
+    store.wait_till_mined(&res.0).await?;
+
+    println!("Allowlist: updated for Contract ID {} ", cid);
+    Ok(())
+}
+
+async fn publish_result<C: HarpocratesContract + DeserializeOwned>(
+    config: &Config,
+    store: &dyn ContractStore,
+    cid: &String,
+    policy: DuplicatePolicy,
+    identity: Identity,
+) -> Result<(), HarpocratesError> {
+    fetch_latest(config, store, cid).await?;
+    let txns = read_transactions(config)?;
+
+    // the tally ignores results signed by anyone else
+    let address = store.eth_address().await?;
+    let deployer = txns.source[0].meta.eth_address.clone();
+    if !address.eq_ignore_ascii_case(&deployer) {
+        return Err(HarpocratesError::Contract(format!(
+            "only the deployer {} can publish the result, not {}",
+            deployer, address
+        )));
+    }
+
+    let source: ContractSource<C> = txns.source[0].contract()?;
+    let public_key = txns.public_key.clone().ok_or_else(|| {
+        HarpocratesError::Key("the contract has no valid published public key".to_string())
+    })?;
+    let (pk, sk) = get_main_keys(config, cid, &source.contract)?;
+
+    let replay = replay(config, txns, &source, &pk, policy, identity, None)?;
+    let decrypted = decrypt(&source.contract, &source.app, &replay.state, &sk)?;
+
+    let result = PublishedResult {
+        tally: source
+            .contract
+            .summary(&decrypted)
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect(),
+        state_sha256: state_hash(&replay.state)?,
+        public_key_sha256: encode_hex(&sha_256(public_key.public_key.as_bytes())),
+        last_interaction: replay.last.ok_or_else(|| {
+            HarpocratesError::Contract(format!("contract {} has no interactions", cid))
+        })?,
+        ballots: replay.counted,
+        duplicates: policy,
+        identity,
+    };
+    // our own check, anyone else will run it
+    result.check(&public_key.public_key, &result.state_sha256, replay.counted)?;
+
+    let res = store
+        .publish_result(cid, serde_json::to_string(&result)?)
+        .await?;
+    println!("Publish Result: Arweave Tx ID: {} ", res.0);
+
+    store.wait_till_mined(&res.0).await?;
+
+    println!(
+        "Publish Result: the tally {:?} of {} ballots is published for Contract ID {} ",
+        result.tally, result.ballots, cid
+    );
+    Ok(())
+}
+
+async fn check_result<C: HarpocratesContract + DeserializeOwned>(
+    config: &Config,
+    store: &dyn ContractStore,
+    cid: &String,
+) -> Result<(), HarpocratesError> {
+    fetch_latest(config, store, cid).await?;
+    let txns = read_transactions(config)?;
+    let deployer = &txns.source[0].meta.eth_address;
+
+    // the latest result the deployer signed
+    let (tx_id, result) = txns
+        .interactions
+        .iter()
+        .rev()
+        .find_map(|intx| match &intx.interaction {
+            Interaction::Decrypt(result)
+                if intx.meta.eth_address.eq_ignore_ascii_case(deployer)
+                    && intx.meta.verify_eth_signature().is_ok() =>
+            {
+                Some((intx.meta.id.clone(), result.clone()))
+            }
+            _ => None,
+        })
+        .ok_or_else(|| {
+            HarpocratesError::Contract(format!(
+                "the deployer {} published no result for contract {}",
+                deployer, cid
+            ))
+        })?;
+
+    // ballots are encrypted to the published key, it is all we need to replay them
+    let source: ContractSource<C> = txns.source[0].contract()?;
+    let public_key = txns.public_key.clone().ok_or_else(|| {
+        HarpocratesError::Key("the contract has no valid published public key".to_string())
+    })?;
+    let pk = public_key.key()?;
+
+    let replay = replay(
+        config,
+        txns,
+        &source,
+        &pk,
+        result.duplicates,
+        result.identity,
+        Some(&result.last_interaction),
+    )?;
+    result.check(
+        &public_key.public_key,
+        &state_hash(&replay.state)?,
+        replay.counted,
+    )?;
+
+    let mut table = Table::new();
+    table.add_row(Row::new(
+        (0..result.tally.len())
+            .map(|i| Cell::new(&option_label(i)))
+            .collect(),
+    ));
+    table.add_row(Row::new(
+        result.tally.iter().map(|value| Cell::new(value)).collect(),
+    ));
+    table.printstd();
+
+    println!(
+        "Check Result: {} is consistent with the {} ballots published up to {}, not necessarily their decryption",
+        tx_id, result.ballots, result.last_interaction
+    );
+    Ok(())
+}
+
+async fn close(store: &dyn ContractStore, cid: &String) -> Result<(), HarpocratesError> {
+    let source_tx = fetch_source_tx(store, cid).await?;
+
+    // the tally ignores a close signed by anyone else
+    let address = store.eth_address().await?;
+    if !address.eq_ignore_ascii_case(&source_tx.meta.eth_address) {
+        return Err(HarpocratesError::Contract(format!(
+            "only the deployer {} can close the poll, not {}",
+            source_tx.meta.eth_address, address
+        )));
+    }
+
+    let res = store.close(cid).await?;
+    println!("Close: Arweave Tx ID: {} ", res.0);
+
+    store.wait_till_mined(&res.0).await?;
+
+    println!("Close: the poll is closed for Contract ID {} ", cid);
+    Ok(())
+}
+
+pub async fn run() -> Result<(), HarpocratesError> {
     let cli = Cli::parse();
     println!();
     println!();
@@ -447,24 +1807,214 @@ pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
     println!();
     println!();
 
-    let _ = match &cli.command {
-        Some(Commands::CreateNewUser {}) => create_new_user(),
-        Some(Commands::Deploy {}) => {
-            deploy().await?;
+    let config = load_config(&cli)?;
+
+    // keys written before there were profiles
+    if config.profiles().adopt_legacy(&config.cache_dir)? {
+        println!(
+            "Keys of {} moved to profile {}",
+            config.cache_dir.display(),
+            DEFAULT_PROFILE
+        );
+    }
+
+    // lives as long as the command, every transaction is lost afterwards
+    let gateway = match cli.local_gateway {
+        true => {
+            let gateway = LocalGateway::start(0, Mining::Instant).await?;
+            println!("Local gateway running at {}", gateway.url());
+            Some(gateway)
+        }
+        false => None,
+    };
+
+    match &cli.command {
+        Some(Commands::CreateNewUser {}) => create_new_user(&config, &VoteContract::default()),
+        Some(Commands::CreateIdentity {}) => create_identity(&config),
+        Some(Commands::SealKeyfile { keyfile, output }) => seal_keyfile(keyfile, output),
+        Some(Commands::Deploy {
+            options,
+            allowlist,
+            allowlist_root,
+            anonymous_allowlist,
+            opens,
+            closes,
+        }) => {
+            let contract = vote_contract(*options)?;
+            let allowlist = read_allowlist(
+                allowlist.as_ref(),
+                allowlist_root.as_ref(),
+                anonymous_allowlist.as_ref(),
+            )?;
+            let window = PollWindow::new(*opens, *closes)?;
+            deploy(
+                &config,
+                open_store(&cli, &config, gateway.as_ref()).await?.as_ref(),
+                &contract,
+                allowlist,
+                window,
+            )
+            .await?;
             Ok(())
         }
-        Some(Commands::InitZkProof { contract_id: id }) => Ok(init_zk(id).await?),
-        Some(Commands::InitState { contract_id: cid }) => Ok(init_state(cid).await?),
-        Some(Commands::FetchLatest { contract_id: cid }) => Ok(fetch_latest(cid).await?),
-        Some(Commands::FetchZk { contract_id: cid }) => Ok(fetch_zk(cid).await?),
-        Some(Commands::ComputeLatest {}) => Ok(compute_latest().await?),
+        Some(Commands::InitZkProof { contract_id: id }) => Ok(init_zk::<VoteContract>(
+            &config,
+            open_store(&cli, &config, gateway.as_ref()).await?.as_ref(),
+            id,
+        )
+        .await?),
+        Some(Commands::InitState { contract_id: cid }) => Ok(init_state::<VoteContract>(
+            &config,
+            open_store(&cli, &config, gateway.as_ref()).await?.as_ref(),
+            cid,
+        )
+        .await?),
+        Some(Commands::FetchLatest { contract_id: cid }) => Ok(fetch_latest(
+            &config,
+            open_store(&cli, &config, gateway.as_ref()).await?.as_ref(),
+            cid,
+        )
+        .await?),
+        Some(Commands::FetchZk { contract_id: cid }) => Ok(fetch_zk(
+            &config,
+            open_store(&cli, &config, gateway.as_ref()).await?.as_ref(),
+            cid,
+        )
+        .await?),
+        Some(Commands::FetchPk { contract_id: cid }) => Ok(fetch_pk(
+            &config,
+            open_store(&cli, &config, gateway.as_ref()).await?.as_ref(),
+            cid,
+        )
+        .await?),
+        Some(Commands::ComputeLatest {
+            duplicates,
+            identity,
+        }) => Ok(compute_latest::<VoteContract>(&config, *duplicates, *identity).await?),
         Some(Commands::Vote {
             contract_id: id,
             number: index,
-        }) => Ok(vote(id, index).await?),
-        Some(Commands::RunAll {}) => Ok(run_all().await?),
+            membership_proof,
+            queue,
+        }) => {
+            let membership = match membership_proof {
+                Some(path) => Some(serde_json::from_slice(&read_file(path)?)?),
+                None => None,
+            };
+            Ok(vote(
+                &config,
+                open_store(&cli, &config, gateway.as_ref()).await?.as_ref(),
+                id,
+                index,
+                membership,
+                queue.as_deref(),
+            )
+            .await?)
+        }
+        Some(Commands::Aggregate {
+            contract_id: cid,
+            queue,
+        }) => Ok(aggregate(
+            &config,
+            open_store(&cli, &config, gateway.as_ref()).await?.as_ref(),
+            cid,
+            &BallotQueue::new(queue.clone()),
+        )
+        .await?),
+        Some(Commands::PublishResult {
+            contract_id: cid,
+            duplicates,
+            identity,
+        }) => Ok(publish_result::<VoteContract>(
+            &config,
+            open_store(&cli, &config, gateway.as_ref()).await?.as_ref(),
+            cid,
+            *duplicates,
+            *identity,
+        )
+        .await?),
+        Some(Commands::CheckResult { contract_id: cid }) => Ok(check_result::<VoteContract>(
+            &config,
+            open_store(&cli, &config, gateway.as_ref()).await?.as_ref(),
+            cid,
+        )
+        .await?),
+        Some(Commands::Close { contract_id: cid }) => Ok(close(
+            open_store(&cli, &config, gateway.as_ref()).await?.as_ref(),
+            cid,
+        )
+        .await?),
+        Some(Commands::Allowlist { command }) => match command {
+            AllowlistCommands::Root { file } => {
+                println!("{}", merkle_root(&read_addresses(file)?)?);
+                Ok(())
+            }
+            AllowlistCommands::Proof { file, address } => allowlist_proof(&config, file, address),
+            AllowlistCommands::Add {
+                contract_id: cid,
+                addresses,
+            } => Ok(update_allowlist::<VoteContract>(
+                open_store(&cli, &config, gateway.as_ref()).await?.as_ref(),
+                cid,
+                addresses,
+                &[],
+            )
+            .await?),
+            AllowlistCommands::Remove {
+                contract_id: cid,
+                addresses,
+            } => Ok(update_allowlist::<VoteContract>(
+                open_store(&cli, &config, gateway.as_ref()).await?.as_ref(),
+                cid,
+                &[],
+                addresses,
+            )
+            .await?),
+        },
+        Some(Commands::Keys { command }) => match command {
+            KeysCommands::List {} => list_keys(&config),
+            KeysCommands::Export { profile, file } => {
+                let keys = config.profiles().export(profile)?;
+                write_private(file, serde_json::to_string(&keys)?.as_bytes())?;
+                println!("Keys of profile {} exported to {}", profile, file.display());
+                Ok(())
+            }
+            KeysCommands::Import { profile, file } => {
+                let keys: ExportedKeys = serde_json::from_slice(&read_file(file)?)?;
+                config.profiles().import(profile, &keys)?;
+                println!("Keys imported as profile {}", profile);
+                Ok(())
+            }
+            KeysCommands::Delete { profile, force } => {
+                for contract_id in config.profiles().delete(profile, *force)? {
+                    println!(
+                        "Contract {} can't be decrypted from here anymore",
+                        contract_id
+                    );
+                }
+                println!("Profile {} deleted", profile);
+                Ok(())
+            }
+            KeysCommands::Admin {
+                contract_id,
+                profile,
+            } => {
+                config.profiles().set_admin(contract_id, profile)?;
+                println!(
+                    "Profile {} is the admin of contract {}",
+                    profile, contract_id
+                );
+                Ok(())
+            }
+        },
+        Some(Commands::RunAll {}) => Ok(run_all(
+            &config,
+            open_store(&cli, &config, gateway.as_ref()).await?.as_ref(),
+            &VoteContract::default(),
+        )
+        .await?),
         None => Ok(()),
-    };
+    }?;
     // show a progress bar as we move along!
     // let pb = indicatif::ProgressBar::new(100);
     // for i in 0..100 {
@@ -475,36 +2025,194 @@ pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn get_main_keys() -> (PublicKey, PrivateKey) {
-    let contract_json = compile().unwrap();
-    let app: Application = serde_json::from_str(&contract_json).unwrap();
+async fn open_store(
+    cli: &Cli,
+    config: &Config,
+    gateway: Option<&LocalGateway>,
+) -> Result<Box<dyn ContractStore>, HarpocratesError> {
+    let signer = match &cli.eth_key {
+        Some(key) => EthSigner::from_private_key(key)?,
+        None => EthSigner::WalletConnect,
+    };
+
+    if let Some(dir) = &cli.local_store {
+        return Ok(Box::new(LocalStore::new(dir.clone(), signer)?));
+    }
+
+    match gateway {
+        Some(gateway) => {
+            let mut config = config.clone();
+            config.gateway = gateway.url();
+            // the local gateway doesn't check balances, any keyfile will do but one is needed to sign
+            if !config.keyfile.exists() {
+                return Err(HarpocratesError::Key(format!(
+                    "no Arweave keyfile at {}, pass one with --keyfile, it doesn't need to hold any AR",
+                    config.keyfile.display()
+                )));
+            }
+            Ok(Box::new(Ar::new(&config, signer).await?))
+        }
+        None => Ok(Box::new(Ar::new(config, signer).await?)),
+    }
+}
+
+// the config file and env vars, overridden by the flags
+fn load_config(cli: &Cli) -> Result<Config, HarpocratesError> {
+    let mut config = Config::load(cli.config.as_deref())?;
+    if let Some(gateway) = &cli.gateway {
+        config.gateway = gateway.clone();
+    }
+    if let Some(keyfile) = &cli.keyfile {
+        config.keyfile = keyfile.clone();
+    }
+    if let Some(cache_dir) = &cli.cache_dir {
+        config.cache_dir = cache_dir.clone();
+    }
+    if let Some(artifacts_dir) = &cli.artifacts_dir {
+        config.artifacts_dir = artifacts_dir.clone();
+    }
+    if let Some(profile) = &cli.profile {
+        config.profile = profile.clone();
+    }
+    Ok(config)
+}
+
+fn vote_contract(options: usize) -> Result<VoteContract, HarpocratesError> {
+    // every ballot is proven against the vote_is_valid circuit, which has a fixed number of inputs
+    if options == 0 || options > VOTE_CIRCUIT_OPTIONS {
+        return Err(HarpocratesError::Contract(format!(
+            "a poll must have between 1 and {} options, got {}",
+            VOTE_CIRCUIT_OPTIONS, options
+        )));
+    }
+    Ok(VoteContract::new(options))
+}
+
+async fn fetch_source_tx(
+    store: &dyn ContractStore,
+    contract_id: &str,
+) -> Result<SourceTx, HarpocratesError> {
+    let mut source = verified_sources(
+        contract_id,
+        store.fetch_source(contract_id.to_string()).await?,
+    )?;
+    if source.is_empty() {
+        return Err(HarpocratesError::Contract(format!(
+            "no source found for contract {}",
+            contract_id
+        )));
+    }
+    Ok(source.remove(0))
+}
+
+// keeps the sources reproducing the contract ID, and refuses the contract if none does
+fn verified_sources(
+    contract_id: &str,
+    sources: Vec<SourceTx>,
+) -> Result<Vec<SourceTx>, HarpocratesError> {
+    let mut error = None;
+    let verified: Vec<SourceTx> = sources
+        .into_iter()
+        .filter(|source| match source.verify() {
+            Ok(()) => true,
+            Err(e) => {
+                error.get_or_insert(e);
+                false
+            }
+        })
+        .collect();
+
+    match error {
+        Some(e) if verified.is_empty() => Err(HarpocratesError::Contract(format!(
+            "contract {} has no valid source: {}",
+            contract_id, e
+        ))),
+        _ => Ok(verified),
+    }
+}
 
-    let runtime = Runtime::new(app.params()).unwrap();
+// the key published for the contract, only trusted if it was signed by whoever deployed it
+async fn fetch_admin_pk(
+    store: &dyn ContractStore,
+    source: &SourceTx,
+) -> Result<PublicKeyTx, HarpocratesError> {
+    let pk = store.fetch_pk(source).await?;
 
-    let pk_string = std::fs::read_to_string("./.cache/pk.json")
-        .expect("Should have been able to read the file");
+    // the key has to be usable
+    pk.key()?;
+    Ok(pk)
+}
 
-    let pk: PublicKey = serde_json::from_str(&pk_string).unwrap();
+// the keys of the profile that deployed the contract, or of the current one
+fn get_main_keys<C: HarpocratesContract>(
+    config: &Config,
+    contract_id: &str,
+    contract: &C,
+) -> Result<(PublicKey, PrivateKey), HarpocratesError> {
+    let contract_json = compile(contract)?;
+    let app: Application = serde_json::from_str(&contract_json)?;
+
+    let runtime = Runtime::new(app.params())?;
+
+    let profiles = config.profiles();
+    let profile = profiles
+        .admin_of(contract_id)?
+        .unwrap_or_else(|| config.profile.clone());
+
+    let pk_string = read_key_file(&profiles.file(&profile, "pk.json")?)?;
+
+    let pk: PublicKey = serde_json::from_str(&pk_string)?;
+
+    let sk_path = profiles.file(&profile, "sk.json")?;
+    let raw = match Sealed::read(&sk_path) {
+        Ok(sealed) => sealed.open(&passphrase("Passphrase of the secret key: ", false)?)?,
+        // written before keys were encrypted, it is encrypted now. Anything else, e.g. a
+        // damaged sealed file, is left as is
+        Err(e) => {
+            let raw = Zeroizing::new(read_key_file(&sk_path)?.into_bytes());
+            if serde_json::from_slice::<SecretKeyFile>(&raw).is_err() {
+                return Err(e);
+            }
+            seal_secret_key(
+                &sk_path,
+                &raw,
+                &format!(
+                    "{} is not encrypted, choose a passphrase: ",
+                    sk_path.display()
+                ),
+            )?;
+            raw
+        }
+    };
+    let secret_k = Zeroizing::new(serde_json::from_slice::<SecretKeyFile>(&raw)?.sk);
 
-    let raw_keys = std::fs::read_to_string("./.cache/sk.json")
-        .expect("Should have been able to read the file");
-    let keys: Value = serde_json::from_str(&raw_keys).unwrap();
-    let secret_k: Vec<u8> = serde_json::from_value(keys["sk"].clone()).unwrap();
+    let sk = runtime
+        .bytes_to_private_key(&secret_k)
+        .map_err(|e| HarpocratesError::Key(format!("invalid secret key: {:?}", e)))?;
 
-    let sk = runtime.bytes_to_private_key(&secret_k).unwrap();
+    Ok((pk, sk))
+}
 
-    (pk, sk)
+fn read_key_file(path: &Path) -> Result<String, HarpocratesError> {
+    fs::read_to_string(path).map_err(|e| {
+        HarpocratesError::Key(format!(
+            "cannot read {} ({}), run create-new-user first",
+            path.display(),
+            e
+        ))
+    })
 }
 
-fn read_file(path: &str) -> std::io::Result<Vec<u8>> {
-    let mut file = File::open(path)?;
-    let mut buf = Vec::new();
-    file.read_to_end(&mut buf)?;
-    return Ok(buf);
+fn read_file(path: &Path) -> Result<Vec<u8>, HarpocratesError> {
+    fs::read(path).map_err(HarpocratesError::cache(path))
 }
 
 // TODO make this interactive
-async fn run_all() -> Result<(), Box<dyn std::error::Error>> {
+async fn run_all(
+    config: &Config,
+    store: &dyn ContractStore,
+    contract: &VoteContract,
+) -> Result<(), HarpocratesError> {
     println!();
     println!();
     println!();
@@ -520,7 +2228,10 @@ async fn run_all() -> Result<(), Box<dyn std::error::Error>> {
     println!("HOW IS THIS POSSIBLE you enthusiastically ask?");
     println!();
     println!("We do this by using Fully Homomorphic Encryption (FHE), ZKSnarks and a decentralized storage solution");
-    println!("Today we will be going through a scenario where we need to vote between 10 different propositions");
+    println!(
+        "Today we will be going through a scenario where we need to vote between {} different propositions",
+        contract.options
+    );
     println!();
     println!();
     println!("I, and I imagine you too, do not want everyone to know who I am voting for. This is where FHE is quite interesting.");
@@ -528,7 +2239,10 @@ async fn run_all() -> Result<(), Box<dyn std::error::Error>> {
 
     println!();
     println!();
-    println!("We will start by creating a set of keys. These keys will be stored in a ./.cache folder in the fhe_contract directory.");
+    println!(
+        "We will start by creating a set of keys. These keys will be stored in the {} folder.",
+        config.cache_dir.display()
+    );
     println!("Most useful files will be stored in this folder, of course after we fetch them from the storage solution first");
 
     prompt_for_any("Press any key to Continue (Ctrl+C to stop)");
@@ -539,8 +2253,12 @@ async fn run_all() -> Result<(), Box<dyn std::error::Error>> {
     println!();
     println!("Creating main user...");
     println!("(this main user is the one that administrates the vote)");
-    // create a new user
-    create_new_user()?;
+    // create a new user, unless the profile already has keys
+    if config.profiles().exists(&config.profile)? {
+        println!("Using the keys of profile {}", config.profile);
+    } else {
+        create_new_user(config, contract)?;
+    }
     println!();
     println!();
     println!();
@@ -576,7 +2294,7 @@ async fn run_all() -> Result<(), Box<dyn std::error::Error>> {
     println!("Deploying contract... this will take some time.");
 
     // deploy contract to arweave
-    let contract_id = deploy().await?;
+    let contract_id = deploy(config, store, contract, None, PollWindow::default()).await?;
 
     clear_screen();
 
@@ -595,7 +2313,7 @@ async fn run_all() -> Result<(), Box<dyn std::error::Error>> {
         "Deploying ZK Params... this will take some time. Again, you will need to sign a message"
     );
     // init zk params
-    init_zk(&contract_id).await?;
+    init_zk::<VoteContract>(config, store, &contract_id).await?;
 
     clear_screen();
 
@@ -615,7 +2333,7 @@ async fn run_all() -> Result<(), Box<dyn std::error::Error>> {
     println!("Deploying initial state... this will take some time");
 
     // init state to the contract
-    init_state(&contract_id).await?;
+    init_state::<VoteContract>(config, store, &contract_id).await?;
 
     clear_screen();
 
@@ -633,7 +2351,7 @@ async fn run_all() -> Result<(), Box<dyn std::error::Error>> {
     println!("Fetching ZK state (kinda fast!)");
 
     // fetch the zk info to populate our cache
-    fetch_zk(&contract_id).await?;
+    fetch_zk(config, store, &contract_id).await?;
 
     clear_screen();
 
@@ -650,9 +2368,13 @@ async fn run_all() -> Result<(), Box<dyn std::error::Error>> {
     println!();
     println!();
     let index = prompt_for_int(
-        "Choose your favorite proposition between 0 and 9 (10 total)",
+        &format!(
+            "Choose your favorite proposition between 0 and {} ({} total)",
+            contract.options - 1,
+            contract.options
+        ),
         0,
-        9,
+        contract.options - 1,
         "out of range you silly goose!",
     );
 
@@ -677,7 +2399,7 @@ async fn run_all() -> Result<(), Box<dyn std::error::Error>> {
     println!("So to recap, we are sending an encrypted version of your vote and a ZKproof to prove that your vote should be valid ");
 
     // vote on who we want
-    vote(&contract_id, &index).await?;
+    vote(config, store, &contract_id, &index, None, None).await?;
 
     clear_screen();
 
@@ -699,7 +2421,7 @@ async fn run_all() -> Result<(), Box<dyn std::error::Error>> {
     println!("Fetching all the transactions for this contract...");
 
     // fetch all the txn, the latest
-    fetch_latest(&contract_id).await?;
+    fetch_latest(config, store, &contract_id).await?;
 
     clear_screen();
 
@@ -732,7 +2454,7 @@ async fn run_all() -> Result<(), Box<dyn std::error::Error>> {
     clear_screen();
 
     // compute the current outcome
-    compute_latest().await?;
+    compute_latest::<VoteContract>(config, DuplicatePolicy::First, Identity::EthAddress).await?;
 
     println!();
     println!();
@@ -803,3 +2525,215 @@ fn prompt_for_int(prompt: &str, min: usize, max: usize, out_of_range_message: &s
 
     int_val
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::{Action, Input};
+    use crate::arweave::ContractType;
+    use crate::testing::{
+        local_store, temp_dir, vote_circuit_is_built, OTHER_ETH_KEY, TEST_ETH_KEY,
+    };
+
+    // a poll with 3 options deployed to a local store, its key published and the zk params of
+    // the circuit in the cache
+    struct Poll {
+        config: Config,
+        // the deployer's
+        store: LocalStore,
+        // someone else's, on the same directory
+        other: LocalStore,
+        contract_id: String,
+        sk: PrivateKey,
+    }
+
+    async fn deploy_poll(name: &str) -> Result<Poll, Box<dyn std::error::Error>> {
+        let root = temp_dir(name);
+
+        let config = Config {
+            cache_dir: root.join("cache"),
+            ..Config::default()
+        };
+        create_cache_dir(&config)?;
+        for file in ["verification_key.json", "vote_is_valid_0001.zkey"] {
+            fs::copy(
+                config.artifact(&format!("circom/{}", file)),
+                config.cache(file),
+            )?;
+        }
+
+        let store = local_store(&root.join("store"), TEST_ETH_KEY);
+        let other = local_store(&root.join("store"), OTHER_ETH_KEY);
+
+        let contract = VoteContract::new(3);
+        let app: Application = serde_json::from_str(&compile(&contract)?)?;
+        let runtime = Runtime::new(app.params())?;
+        let (pk, sk) = runtime.generate_keys()?;
+
+        let source = serde_json::to_string(&ContractSource {
+            app,
+            contract,
+            allowlist: None,
+            window: PollWindow::default(),
+            nonce: source_nonce(),
+        })?;
+        let (_, contract_id) = store.deploy_contract(&source).await?;
+        store
+            .publish_pk(&contract_id, serde_json::to_string(&pk)?)
+            .await?;
+        // only their transaction ID is checked by the tally, the keys are copied above
+        store
+            .deploy_zksnark(&contract_id, b"zk params".to_vec())
+            .await?;
+
+        Ok(Poll {
+            config,
+            store,
+            other,
+            contract_id,
+            sk,
+        })
+    }
+
+    async fn init_poll(store: &LocalStore, contract_id: &str) -> Result<String, HarpocratesError> {
+        let source_tx = fetch_source_tx(store, contract_id).await?;
+        let source: ContractSource<VoteContract> = source_tx.contract()?;
+        let pk = fetch_admin_pk(store, &source_tx).await?.key()?;
+        let init = get_initial_state(&source.contract, &source.app, &pk)?;
+        Ok(store.initialize_state(contract_id, init).await?.0)
+    }
+
+    // what anyone tallying the poll gets, counting the first vote of every address
+    async fn replay_poll(
+        config: &Config,
+        store: &LocalStore,
+        contract_id: &str,
+    ) -> Result<(Replay, ContractSource<VoteContract>), HarpocratesError> {
+        fetch_latest(config, store, &contract_id.to_string()).await?;
+        let txns = read_transactions(config)?;
+        let source: ContractSource<VoteContract> = txns.source[0].contract()?;
+        let pk = txns
+            .public_key
+            .clone()
+            .ok_or_else(|| HarpocratesError::Key("no public key".to_string()))?
+            .key()?;
+        let replayed = replay(
+            config,
+            txns,
+            &source,
+            &pk,
+            DuplicatePolicy::First,
+            Identity::EthAddress,
+            None,
+        )?;
+        Ok((replayed, source))
+    }
+
+    #[tokio::test]
+    async fn it_drops_what_the_tally_cannot_trust() -> Result<(), Box<dyn std::error::Error>> {
+        let Poll {
+            config,
+            store,
+            other,
+            contract_id,
+            ..
+        } = deploy_poll("drops").await?;
+
+        // someone else starts the tally from a state of their own, before the deployer does
+        let squatted = init_poll(&other, &contract_id).await?;
+        init_poll(&store, &contract_id).await?;
+
+        // a vote tagged with the deployer's address, signed by someone else
+        let data = b"{}".to_vec();
+        let (address, _) = store.eth_sign(&data).await?;
+        let (_, signature) = other.eth_sign(&data).await?;
+        let forged = store
+            .publish_tagged(
+                &contract_id,
+                &Input::new(Action::Vote),
+                ContractType::Interaction,
+                data,
+                &address,
+                &signature,
+            )
+            .await?;
+
+        // a vote proven with the keys of someone else's ceremony
+        let (foreign_zk, _) = other
+            .deploy_zksnark(&contract_id, b"other zk params".to_vec())
+            .await?;
+        let (unproven, _) = other
+            .publish(
+                &contract_id,
+                Input::new(Action::Vote).proven_by(Some(foreign_zk.clone())),
+                ContractType::Interaction,
+                b"{}".to_vec(),
+            )
+            .await?;
+
+        store.close(&contract_id).await?;
+        let (late, _) = other.vote(&contract_id, "{}".to_string()).await?;
+
+        let (replayed, _) = replay_poll(&config, &store, &contract_id).await?;
+        let reason = |tx_id: &str| {
+            replayed
+                .dropped
+                .iter()
+                .find(|(id, _)| id == tx_id)
+                .map(|(_, reason)| reason.clone())
+                .unwrap_or_default()
+        };
+        assert!(reason(&squatted).starts_with("only the deployer"));
+        assert_eq!(
+            reason(&forged),
+            format!(
+                "signature error: signed by {} instead of {}",
+                other.eth_address().await?.to_lowercase(),
+                address.to_lowercase()
+            )
+        );
+        assert!(reason(&unproven).starts_with(&format!("proven with the zk params {}", foreign_zk)));
+        assert!(reason(&late).starts_with("sent after the poll was closed"));
+        assert_eq!(replayed.dropped.len(), 4);
+        assert_eq!(replayed.counted, 0);
+        assert!(replayed.closed.is_some());
+        Ok(())
+    }
+
+    // proves every vote in-process, so it needs the circuit and its keys as built by make
+    #[tokio::test]
+    async fn it_counts_one_vote_per_voter() -> Result<(), Box<dyn std::error::Error>> {
+        if !vote_circuit_is_built() {
+            return Ok(());
+        }
+        let Poll {
+            config,
+            store,
+            other,
+            contract_id,
+            sk,
+        } = deploy_poll("duplicates").await?;
+        init_poll(&store, &contract_id).await?;
+
+        vote(&config, &store, &contract_id, &1, None, None).await?;
+        vote(&config, &other, &contract_id, &2, None, None).await?;
+        // only the first vote of the deployer counts
+        vote(&config, &store, &contract_id, &0, None, None).await?;
+
+        let (replayed, source) = replay_poll(&config, &store, &contract_id).await?;
+        assert!(replayed.dropped.is_empty(), "{:?}", replayed.dropped);
+        assert_eq!(replayed.counted, 2);
+        assert_eq!(replayed.duplicates.len(), 1);
+        assert_eq!(
+            replayed.duplicates[0].identity,
+            store.eth_address().await?.to_lowercase()
+        );
+
+        let tally = decrypt(&source.contract, &source.app, &replayed.state, &sk)?;
+        assert_eq!(
+            tally.iter().map(|t| t.to_string()).collect::<Vec<_>>(),
+            vec!["0", "1", "1"]
+        );
+        Ok(())
+    }
+}
@@ -1,12 +1,16 @@
-use crate::contract::add_vote;
-use std::io::prelude::*;
-use std::{error::Error, fs::File};
-use sunscreen::{Compiler, FheProgramFn};
-pub fn compile_and_save_contract() -> Result<(), Box<dyn Error>> {
-    let ser_app_json = compile().unwrap();
+use crate::contract::HarpocratesContract;
+use crate::error::HarpocratesError;
+use std::fs;
+use std::path::Path;
+use sunscreen::Compiler;
+pub fn compile_and_save_contract<C: HarpocratesContract>(
+    contract: &C,
+    cache_dir: &Path,
+) -> Result<(), HarpocratesError> {
+    let ser_app_json = compile(contract)?;
     // we output it to a target folder
-    let mut file = File::create(format!("./.cache/{}_params.json", add_vote.name()))?;
-    file.write_all(ser_app_json.as_bytes())?;
+    let path = cache_dir.join(format!("{}_params.json", contract.name()));
+    fs::write(&path, ser_app_json.as_bytes()).map_err(HarpocratesError::cache(path))?;
 
     // reserializes the app
     // let new_app: Application = serde_json::from_str(&ser_app_json).unwrap();
@@ -17,12 +21,12 @@ pub fn compile_and_save_contract() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-pub fn compile() -> Result<String, Box<dyn Error>> {
-    // first we compile the app
-    let app = Compiler::new().fhe_program(add_vote).compile().unwrap();
+pub fn compile<C: HarpocratesContract>(contract: &C) -> Result<String, HarpocratesError> {
+    // first we compile the app with all the programs of the contract
+    let app = contract.programs(Compiler::new()).compile()?;
 
     // we then serialize it to json (can be bincode too, json for clarity)
-    let ser_app_json = serde_json::to_string(&app).unwrap();
+    let ser_app_json = serde_json::to_string(&app)?;
 
     Ok(ser_app_json)
 }
@@ -30,9 +34,11 @@ pub fn compile() -> Result<String, Box<dyn Error>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Config;
+    use crate::contract::VoteContract;
 
     #[test]
-    fn it_works() -> Result<(), Box<dyn Error>> {
-        compile_and_save_contract()
+    fn it_works() -> Result<(), HarpocratesError> {
+        compile_and_save_contract(&VoteContract::default(), &Config::default().cache_dir)
     }
 }
@@ -0,0 +1,127 @@
+use crate::error::HarpocratesError;
+use crate::profile::{Profiles, DEFAULT_PROFILE};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+// read when no config file is given explicitly, if it exists
+pub const DEFAULT_CONFIG_FILE: &str = "./harpocrates.toml";
+
+/// Where harpocrates talks to and where it keeps its files.
+///
+/// Layered from lowest to highest priority: defaults, the TOML config file,
+/// `HARPOCRATES_*` environment variables and finally the command line flags.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Config {
+    /// Arweave gateway, the GraphQL endpoint is `<gateway>/graphql`
+    pub gateway: Url,
+    /// Arweave wallet paying for the transactions
+    pub keyfile: PathBuf,
+    /// Keys, fetched transactions, zk params and proofs
+    pub cache_dir: PathBuf,
+    /// Holds the compiled circuits and their keys (`circom/`)
+    pub artifacts_dir: PathBuf,
+    /// Keys to use, from `<cache_dir>/profiles/`
+    pub profile: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            gateway: Url::parse("https://arweave.net").expect("valid url"),
+            keyfile: PathBuf::from("./arweave-keyfile.json"),
+            cache_dir: PathBuf::from("./.cache"),
+            artifacts_dir: PathBuf::from("."),
+            profile: DEFAULT_PROFILE.to_string(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads `file` (or `HARPOCRATES_CONFIG`, or `./harpocrates.toml` when present)
+    /// then applies the environment variables on top of it
+    pub fn load(file: Option<&Path>) -> Result<Self, HarpocratesError> {
+        let file = file
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HARPOCRATES_CONFIG").map(PathBuf::from));
+
+        let config = match file {
+            Some(path) => Config::from_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Config::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Config::default(),
+        };
+
+        config.with_vars(std::env::vars())
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, HarpocratesError> {
+        let raw = fs::read_to_string(path).map_err(HarpocratesError::cache(path))?;
+        toml::from_str(&raw).map_err(|e| {
+            HarpocratesError::Serialization(format!("invalid config {}: {}", path.display(), e))
+        })
+    }
+
+    /// Overrides the values set by `HARPOCRATES_GATEWAY`, `HARPOCRATES_KEYFILE`,
+    /// `HARPOCRATES_CACHE_DIR`, `HARPOCRATES_ARTIFACTS_DIR` and `HARPOCRATES_PROFILE`
+    pub fn with_vars<I>(mut self, vars: I) -> Result<Self, HarpocratesError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        for (name, value) in vars {
+            match name.as_str() {
+                "HARPOCRATES_GATEWAY" => self.gateway = Url::parse(&value)?,
+                "HARPOCRATES_KEYFILE" => self.keyfile = PathBuf::from(value),
+                "HARPOCRATES_CACHE_DIR" => self.cache_dir = PathBuf::from(value),
+                "HARPOCRATES_ARTIFACTS_DIR" => self.artifacts_dir = PathBuf::from(value),
+                "HARPOCRATES_PROFILE" => self.profile = value,
+                _ => {}
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn cache(&self, name: &str) -> PathBuf {
+        self.cache_dir.join(name)
+    }
+
+    pub fn artifact(&self, name: &str) -> PathBuf {
+        self.artifacts_dir.join(name)
+    }
+
+    pub fn profiles(&self) -> Profiles {
+        Profiles::new(self.cache_dir.join("profiles"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_layers_file_and_env() -> Result<(), HarpocratesError> {
+        let path = std::env::temp_dir().join("harpocrates-config-test.toml");
+        fs::write(
+            &path,
+            "gateway = \"http://localhost:1984\"\ncache-dir = \"/tmp/from-file\"\n",
+        )
+        .map_err(HarpocratesError::cache(&path))?;
+
+        let config = Config::from_file(&path)?.with_vars(vec![(
+            "HARPOCRATES_CACHE_DIR".to_string(),
+            "/tmp/from-env".to_string(),
+        )])?;
+
+        assert_eq!(config.gateway.as_str(), "http://localhost:1984/");
+        assert_eq!(
+            config.cache("pk.json"),
+            PathBuf::from("/tmp/from-env/pk.json")
+        );
+        // untouched values keep their default
+        assert_eq!(config.keyfile, Config::default().keyfile);
+        Ok(())
+    }
+}
@@ -1,19 +1,203 @@
+use crate::allowlist::Allowlist;
+use crate::arweave::encode_hex;
+use crate::error::HarpocratesError;
+use crate::poll::PollWindow;
+use ark_std::rand::{thread_rng, RngCore};
+use serde::{Deserialize, Serialize};
 use sunscreen::{
     fhe_program,
     types::{bfv::Signed, Cipher},
+    Application, Ciphertext, Compiler, Error, FheProgramFn, PrivateKey, PublicKey, Runtime,
 };
 
-#[fhe_program(scheme = "bfv")]
-pub fn add_vote(
-    curr_votes: [Cipher<Signed>; 10],
-    vote: [Cipher<Signed>; 10],
-) -> [Cipher<Signed>; 10] {
-    let mut curr = curr_votes.clone();
-    for i in 0..10 {
-        curr[i] = curr[i] + vote[i];
+/// An FHE contract that can be compiled, deployed and replayed by harpocrates.
+///
+/// The contract state is a list of ciphertexts, every interaction is run through
+/// the `step` program element by element as `(current_state[i], input[i]) -> new_state[i]`.
+pub trait HarpocratesContract {
+    /// Plaintext value a participant submits to the contract
+    type Input;
+    /// Plaintext value of the decrypted contract state
+    type Output;
+
+    /// Name of the contract, used to name the compiled params
+    fn name(&self) -> String;
+
+    /// Registers all the FHE programs of this contract to the compiler
+    fn programs(&self, compiler: Compiler) -> Compiler;
+
+    /// Name of the program run on every interaction
+    fn step(&self) -> String;
+
+    /// Name of the program taking an input run through `step` back out of the state,
+    /// used to replace or reject an earlier input of the same participant
+    fn undo(&self) -> Option<String> {
+        None
+    }
+
+    /// Number of ciphertexts making up the state (and every input)
+    fn width(&self) -> usize;
+
+    /// Encrypted state the contract starts from
+    fn initial_state(&self, runtime: &Runtime, pk: &PublicKey) -> Result<Vec<Ciphertext>, Error>;
+
+    /// Encrypts a participant's input so it can be fed to `step`, fails on an input the
+    /// contract doesn't take
+    fn encode_input(
+        &self,
+        runtime: &Runtime,
+        pk: &PublicKey,
+        input: Self::Input,
+    ) -> Result<Vec<Ciphertext>, HarpocratesError>;
+
+    /// Decrypts the contract state
+    fn decode_output(
+        &self,
+        runtime: &Runtime,
+        sk: &PrivateKey,
+        state: &[Ciphertext],
+    ) -> Result<Self::Output, Error>;
+
+    /// Header and value of every column shown when displaying the output
+    fn summary(&self, output: &Self::Output) -> Vec<(String, String)>;
+}
+
+/// Data published in the source transaction of a contract: the compiled
+/// application along with the parameters the contract was deployed with
+#[derive(Serialize, Deserialize)]
+pub struct ContractSource<C> {
+    pub app: Application,
+    pub contract: C,
+    /// Who may vote, anyone can if there is none
+    #[serde(default)]
+    pub allowlist: Option<Allowlist>,
+    /// When votes count, they always do if it is empty
+    #[serde(default)]
+    pub window: PollWindow,
+    /// Random, so that deploying the same contract twice gives two contract IDs
+    #[serde(default)]
+    pub nonce: String,
+}
+
+/// A new nonce for a `ContractSource`
+pub fn source_nonce() -> String {
+    let mut nonce = [0u8; 16];
+    thread_rng().fill_bytes(&mut nonce);
+    encode_hex(&nonce)
+}
+
+/// Voting contract, tallies the votes for a number of propositions chosen at deploy time
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct VoteContract {
+    pub options: usize,
+}
+
+impl Default for VoteContract {
+    fn default() -> Self {
+        VoteContract { options: 10 }
     }
+}
 
-    curr
+impl VoteContract {
+    pub fn new(options: usize) -> Self {
+        VoteContract { options }
+    }
+
+    /// One hot encoding of a vote for the proposition `index`
+    pub fn ballot(&self, index: usize) -> Result<Vec<i64>, HarpocratesError> {
+        if index >= self.options {
+            return Err(HarpocratesError::Contract(format!(
+                "there is no proposition {}, the contract has {}",
+                index, self.options
+            )));
+        }
+        let mut vote = vec![0; self.options];
+        vote[index] = 1;
+        Ok(vote)
+    }
+}
+
+/// Column name of a proposition, A to Z then its index
+pub fn option_label(index: usize) -> String {
+    if index < 26 {
+        ((b'A' + index as u8) as char).to_string()
+    } else {
+        index.to_string()
+    }
+}
+
+impl HarpocratesContract for VoteContract {
+    // index of the proposition voted for
+    type Input = usize;
+    type Output = Vec<Signed>;
+
+    fn name(&self) -> String {
+        add_vote.name().to_string()
+    }
+
+    fn programs(&self, compiler: Compiler) -> Compiler {
+        compiler.fhe_program(add_vote).fhe_program(remove_vote)
+    }
+
+    fn step(&self) -> String {
+        add_vote.name().to_string()
+    }
+
+    fn undo(&self) -> Option<String> {
+        Some(remove_vote.name().to_string())
+    }
+
+    fn width(&self) -> usize {
+        self.options
+    }
+
+    fn initial_state(&self, runtime: &Runtime, pk: &PublicKey) -> Result<Vec<Ciphertext>, Error> {
+        (0..self.options)
+            .map(|_| runtime.encrypt(Signed::from(0), pk))
+            .collect()
+    }
+
+    fn encode_input(
+        &self,
+        runtime: &Runtime,
+        pk: &PublicKey,
+        input: Self::Input,
+    ) -> Result<Vec<Ciphertext>, HarpocratesError> {
+        Ok(self
+            .ballot(input)?
+            .into_iter()
+            .map(|x| runtime.encrypt(Signed::from(x), pk))
+            .collect::<Result<_, _>>()?)
+    }
+
+    fn decode_output(
+        &self,
+        runtime: &Runtime,
+        sk: &PrivateKey,
+        state: &[Ciphertext],
+    ) -> Result<Self::Output, Error> {
+        state.iter().map(|c| runtime.decrypt(c, sk)).collect()
+    }
+
+    fn summary(&self, output: &Self::Output) -> Vec<(String, String)> {
+        output
+            .iter()
+            .enumerate()
+            .map(|(i, tally)| (option_label(i), tally.to_string()))
+            .collect()
+    }
+}
+
+// adds a vote to the tally of a single proposition
+#[fhe_program(scheme = "bfv")]
+pub fn add_vote(curr_votes: Cipher<Signed>, vote: Cipher<Signed>) -> Cipher<Signed> {
+    curr_votes + vote
+}
+
+// takes a vote back out of the tally of a single proposition
+#[fhe_program(scheme = "bfv")]
+pub fn remove_vote(curr_votes: Cipher<Signed>, vote: Cipher<Signed>) -> Cipher<Signed> {
+    curr_votes - vote
 }
 
 #[cfg(test)]
@@ -29,37 +213,9 @@ mod tests {
 
         let (counter_pk, counter_sk) = runtime.generate_keys()?;
 
-        let init_state = runtime.encrypt(
-            [
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-            ],
-            &counter_pk,
-        )?;
+        let init_state = runtime.encrypt(Signed::from(0), &counter_pk)?;
 
-        let alice_vote = runtime.encrypt(
-            [
-                Signed::from(1),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-            ],
-            &counter_pk,
-        )?;
+        let alice_vote = runtime.encrypt(Signed::from(1), &counter_pk)?;
 
         let first_result = runtime.run(
             app.get_program(add_vote).unwrap(),
@@ -67,25 +223,11 @@ mod tests {
             &counter_pk,
         )?;
 
-        // let c: [Signed; 10] = runtime.decrypt(&first_result[0], &counter_sk)?;
+        // let c: Signed = runtime.decrypt(&first_result[0], &counter_sk)?;
         // intermediate result
 
         // now bob votes
-        let bob_vote = runtime.encrypt(
-            [
-                Signed::from(1),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-                Signed::from(0),
-            ],
-            &counter_pk,
-        )?;
+        let bob_vote = runtime.encrypt(Signed::from(1), &counter_pk)?;
 
         let fr = first_result[0].clone();
 
@@ -95,9 +237,50 @@ mod tests {
             &counter_pk,
         )?;
 
-        let final_tally: [Signed; 10] = runtime.decrypt(&final_result[0], &counter_sk)?;
+        let final_tally: Signed = runtime.decrypt(&final_result[0], &counter_sk)?;
+
+        assert_eq!(final_tally.to_string(), "2".to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn it_runs_through_the_contract_trait() -> Result<(), HarpocratesError> {
+        let contract = VoteContract::new(4);
+        let app = contract.programs(Compiler::new()).compile()?;
+
+        let runtime = Runtime::new(app.params())?;
+
+        let (pk, sk) = runtime.generate_keys()?;
+
+        let init_state = contract.initial_state(&runtime, &pk)?;
+        let alice_vote = contract.encode_input(&runtime, &pk, 3)?;
+
+        assert_eq!(init_state.len(), 4);
+        assert_eq!(alice_vote.len(), 4);
+
+        let mut result = vec![];
+        for (curr, vote) in init_state.into_iter().zip(alice_vote) {
+            let r = runtime.run(
+                app.get_program(contract.step()).unwrap(),
+                vec![curr, vote],
+                &pk,
+            )?;
+            result.push(r[0].clone());
+        }
+
+        let tally = contract.decode_output(&runtime, &sk, &result)?;
+
+        assert_eq!(tally[3].to_string(), "1".to_string());
+        assert_eq!(
+            contract.summary(&tally)[3],
+            ("D".to_string(), "1".to_string())
+        );
 
-        assert_eq!(final_tally[0].to_string(), "2".to_string());
+        // there is no fifth proposition
+        assert!(matches!(
+            contract.encode_input(&runtime, &pk, 4),
+            Err(HarpocratesError::Contract(_))
+        ));
         Ok(())
     }
 }
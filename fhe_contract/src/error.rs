@@ -0,0 +1,91 @@
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Everything that can go wrong while deploying, voting on or tallying a contract
+#[derive(Debug, Error)]
+pub enum HarpocratesError {
+    /// The gateway could not be reached, or answered with something we don't understand
+    #[error("gateway error: {0}")]
+    Gateway(String),
+
+    /// Some data (a transaction, a cache file, the zk params) is not in the expected format
+    #[error("serialization error: {0}")]
+    Serialization(String),
+
+    /// A ZK proof could not be generated or verified
+    #[error("proof error: {0}")]
+    Proof(String),
+
+    /// An Ethereum signature could not be produced or checked
+    #[error("signature error: {0}")]
+    Signature(String),
+
+    /// A key is missing or invalid
+    #[error("key error: {0}")]
+    Key(String),
+
+    /// The contract or its parameters are missing or invalid
+    #[error("contract error: {0}")]
+    Contract(String),
+
+    #[error("FHE runtime error: {0}")]
+    Fhe(#[from] sunscreen::Error),
+
+    /// A local file (cache, local store, artifacts or config) could not be read or written
+    #[error("cannot access {}: {source}", path.display())]
+    Cache { path: PathBuf, source: io::Error },
+}
+
+impl HarpocratesError {
+    // to be used as `.map_err(HarpocratesError::cache(path))`
+    pub(crate) fn cache<P: Into<PathBuf>>(path: P) -> impl FnOnce(io::Error) -> Self {
+        let path = path.into();
+        move |source| HarpocratesError::Cache { path, source }
+    }
+}
+
+impl From<serde_json::Error> for HarpocratesError {
+    fn from(e: serde_json::Error) -> Self {
+        HarpocratesError::Serialization(e.to_string())
+    }
+}
+
+impl From<bincode::Error> for HarpocratesError {
+    fn from(e: bincode::Error) -> Self {
+        HarpocratesError::Serialization(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for HarpocratesError {
+    fn from(e: reqwest::Error) -> Self {
+        HarpocratesError::Gateway(e.to_string())
+    }
+}
+
+impl From<arloader::error::Error> for HarpocratesError {
+    fn from(e: arloader::error::Error) -> Self {
+        HarpocratesError::Gateway(e.to_string())
+    }
+}
+
+impl From<url::ParseError> for HarpocratesError {
+    fn from(e: url::ParseError) -> Self {
+        HarpocratesError::Gateway(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_names_the_missing_cache_file() {
+        let err = std::fs::read("./.cache/does-not-exist.json")
+            .map_err(HarpocratesError::cache("./.cache/does-not-exist.json"))
+            .unwrap_err();
+
+        assert!(matches!(err, HarpocratesError::Cache { .. }));
+        assert!(err.to_string().contains("./.cache/does-not-exist.json"));
+    }
+}
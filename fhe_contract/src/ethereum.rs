@@ -1,6 +1,66 @@
+use crate::error::HarpocratesError;
+use ethers::signers::{LocalWallet, Signer};
+use ethers::types::{Address, Signature};
 use std::error::Error;
+use std::str::FromStr;
 use walletconnect::{qr, Client, Metadata};
 
+/// Who signs the `Eth-Signature` tag of a transaction
+pub enum EthSigner {
+    /// Asks a WalletConnect wallet to sign (prints a QR code)
+    WalletConnect,
+    /// Signs with a private key held in memory, meant for local runs and tests
+    Local(LocalWallet),
+}
+
+impl EthSigner {
+    pub fn from_private_key(key: &str) -> Result<Self, HarpocratesError> {
+        let wallet: LocalWallet = key
+            .trim_start_matches("0x")
+            .parse()
+            .map_err(|e| HarpocratesError::Key(format!("invalid Ethereum private key: {}", e)))?;
+        Ok(EthSigner::Local(wallet))
+    }
+
+    /// Address the signatures are made with
+    pub async fn eth_address(&self) -> Result<String, HarpocratesError> {
+        match self {
+            EthSigner::WalletConnect => Ok(EthClient::new()
+                .await
+                .map_err(|e| HarpocratesError::Signature(e.to_string()))?
+                .account()),
+            EthSigner::Local(wallet) => Ok(format!("{:?}", wallet.address())),
+        }
+    }
+
+    /// Signs the message built by `message` from the signer's address,
+    /// returns the address and the signature
+    pub async fn sign_with<F>(&self, message: F) -> Result<(String, String), HarpocratesError>
+    where
+        F: FnOnce(&str) -> String,
+    {
+        match self {
+            EthSigner::WalletConnect => {
+                let c = EthClient::new()
+                    .await
+                    .map_err(|e| HarpocratesError::Signature(e.to_string()))?;
+                let to_sign = message(&c.account());
+                c.get_sig(&to_sign)
+                    .await
+                    .map_err(|e| HarpocratesError::Signature(e.to_string()))
+            }
+            EthSigner::Local(wallet) => {
+                let account = format!("{:?}", wallet.address());
+                let sig = wallet
+                    .sign_message(message(&account))
+                    .await
+                    .map_err(|e| HarpocratesError::Signature(e.to_string()))?;
+                Ok((account, format!("{}", sig)))
+            }
+        }
+    }
+}
+
 pub struct EthClient {
     client: Client,
     account: String,
@@ -36,31 +96,59 @@ impl EthClient {
     }
 }
 
-#[allow(unused)]
-fn verify_sig(message: &str, sig: &[u8]) -> Result<String, Box<dyn Error>> {
-    let addr = walletconnect::client::verify_sig(message, sig)?;
-    Ok(addr)
+/// Checks that `signature` is a personal_sign of `message` by `address`
+pub fn verify_sig(message: &str, signature: &str, address: &str) -> Result<(), HarpocratesError> {
+    let expected = Address::from_str(address)
+        .map_err(|e| HarpocratesError::Signature(format!("invalid address {}: {}", address, e)))?;
+    let signature = Signature::from_str(signature)
+        .map_err(|e| HarpocratesError::Signature(format!("invalid signature: {}", e)))?;
+
+    let signer = signature
+        .recover(message)
+        .map_err(|e| HarpocratesError::Signature(format!("cannot recover the signer: {}", e)))?;
+
+    if signer != expected {
+        return Err(HarpocratesError::Signature(format!(
+            "signed by {:?} instead of {:?}",
+            signer, expected
+        )));
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-
-    use walletconnect::ethers_core::utils::{hex, keccak256};
-
     use super::*;
+    use crate::testing::{OTHER_ETH_KEY, TEST_ETH_KEY};
 
     #[tokio::test]
     async fn it_runs() -> Result<(), Box<dyn std::error::Error>> {
         let msg = "hello";
-        let h = keccak256(msg.as_bytes());
-        let message_hash = hex::encode(&h);
 
         let c = EthClient::new().await.unwrap();
         let (acc, sig) = c.get_sig(msg).await.unwrap();
         println!("{}", acc);
         println!("{}", sig);
 
-        verify_sig(&message_hash, sig.as_bytes())?;
+        verify_sig(msg, &sig, &acc)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_verifies_local_signatures() -> Result<(), HarpocratesError> {
+        let (account, sig) = EthSigner::from_private_key(TEST_ETH_KEY)?
+            .sign_with(|account| format!("hello {}", account))
+            .await?;
+        let message = format!("hello {}", account);
+
+        verify_sig(&message, &sig, &account)?;
+
+        // someone else's address, or another message
+        let (other, _) = EthSigner::from_private_key(OTHER_ETH_KEY)?
+            .sign_with(|_| String::new())
+            .await?;
+        assert!(verify_sig(&message, &sig, &other).is_err());
+        assert!(verify_sig("hello", &sig, &account).is_err());
         Ok(())
     }
 }
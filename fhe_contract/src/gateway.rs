@@ -0,0 +1,436 @@
+use crate::arweave::sha_256;
+use crate::error::HarpocratesError;
+use arloader::transaction::Base64;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+use url::Url;
+
+// number of edges returned per GraphQL page, same as arweave.net
+const PAGE_SIZE: usize = 10;
+
+/// How the local gateway mines the transactions posted to it
+#[derive(Clone, Copy, Debug)]
+pub enum Mining {
+    /// Every transaction is mined in its own block as soon as it is posted
+    Instant,
+    /// Transactions stay pending until `LocalGateway::mine` is called
+    Manual,
+    /// Pending transactions are mined together in a new block at every interval
+    Interval(Duration),
+}
+
+struct Tx {
+    id: String,
+    owner: String,
+    owner_key: String,
+    tags: Vec<(String, String)>,
+    data: Vec<u8>,
+    height: Option<u64>,
+}
+
+struct Block {
+    height: u64,
+    timestamp: u64,
+    indep_hash: String,
+}
+
+#[derive(Default)]
+struct Chain {
+    txs: Vec<Tx>,
+    blocks: Vec<Block>,
+}
+
+impl Chain {
+    fn height(&self) -> u64 {
+        self.blocks.len() as u64
+    }
+
+    // puts every pending transaction in a new block
+    fn mine(&mut self) {
+        if self.txs.iter().all(|tx| tx.height.is_some()) {
+            return;
+        }
+
+        let height = self.height() + 1;
+        for tx in self.txs.iter_mut().filter(|tx| tx.height.is_none()) {
+            tx.height = Some(height);
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let indep_hash = sha_256(format!("block{}", height).as_bytes());
+        self.blocks.push(Block {
+            height,
+            timestamp,
+            indep_hash: Base64(indep_hash.to_vec()).to_string(),
+        });
+    }
+
+    fn tx(&self, id: &str) -> Option<&Tx> {
+        self.txs.iter().find(|tx| tx.id == id)
+    }
+
+    fn block(&self, height: u64) -> &Block {
+        &self.blocks[(height - 1) as usize]
+    }
+}
+
+/// In-process stand-in for an Arweave gateway.
+///
+/// Implements the subset of the Arweave HTTP and GraphQL API that `Ar` uses:
+/// `POST /tx`, `GET /tx/{id}/status`, `GET /tx_anchor`, `GET /price/{bytes}`,
+/// `GET /{id}` and `GET /{id}/data.json`, and `POST /graphql` filtered by tags and
+/// paginated with cursors. Signatures and balances are not checked, and GraphQL only
+/// returns mined transactions. Everything is kept in memory and lost once dropped.
+pub struct LocalGateway {
+    url: Url,
+    chain: Arc<Mutex<Chain>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl LocalGateway {
+    /// Starts the gateway on `127.0.0.1:port`, port 0 picks any free port
+    pub async fn start(port: u16, mining: Mining) -> Result<Self, HarpocratesError> {
+        let chain = Arc::new(Mutex::new(Chain::default()));
+
+        let service_chain = chain.clone();
+        let make_svc = make_service_fn(move |_conn| {
+            let chain = service_chain.clone();
+            let service = service_fn(move |req| handle(chain.clone(), mining, req));
+            async move { Ok::<_, Infallible>(service) }
+        });
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        let server = Server::try_bind(&addr)
+            .map_err(|e| HarpocratesError::Gateway(format!("cannot bind {}: {}", addr, e)))?
+            .serve(make_svc);
+        let url = Url::parse(&format!("http://{}/", server.local_addr()))?;
+
+        let mut tasks = vec![tokio::spawn(async move {
+            if let Err(e) = server.await {
+                println!("Local gateway stopped: {}", e);
+            }
+        })];
+
+        if let Mining::Interval(interval) = mining {
+            let chain = chain.clone();
+            tasks.push(tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(interval).await;
+                    chain.lock().unwrap().mine();
+                }
+            }));
+        }
+
+        Ok(LocalGateway { url, chain, tasks })
+    }
+
+    pub fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    /// Mines every pending transaction in a new block
+    pub fn mine(&self) {
+        self.chain.lock().unwrap().mine();
+    }
+
+    pub fn height(&self) -> u64 {
+        self.chain.lock().unwrap().height()
+    }
+}
+
+impl Drop for LocalGateway {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+async fn handle(
+    chain: Arc<Mutex<Chain>>,
+    mining: Mining,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let method = req.method().clone();
+    let path: Vec<String> = req
+        .uri()
+        .path()
+        .trim_matches('/')
+        .split('/')
+        .map(|p| p.to_string())
+        .collect();
+
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return Ok(respond(StatusCode::BAD_REQUEST, e.to_string())),
+    };
+
+    let mut chain = chain.lock().unwrap();
+    let path: Vec<&str> = path.iter().map(|p| p.as_str()).collect();
+
+    let response = match (method, path.as_slice()) {
+        (Method::POST, ["tx"]) => match parse_tx(&body) {
+            Ok(tx) => {
+                chain.txs.push(tx);
+                if let Mining::Instant = mining {
+                    chain.mine();
+                }
+                respond(StatusCode::OK, "OK")
+            }
+            Err(e) => respond(StatusCode::BAD_REQUEST, e.to_string()),
+        },
+        (Method::GET, ["tx", id, "status"]) => match chain.tx(id) {
+            Some(Tx {
+                height: Some(height),
+                ..
+            }) => respond(
+                StatusCode::OK,
+                json!({
+                    "block_height": height,
+                    "block_indep_hash": chain.block(*height).indep_hash,
+                    "number_of_confirmations": chain.height() - height + 1,
+                })
+                .to_string(),
+            ),
+            Some(_) => respond(StatusCode::ACCEPTED, "Pending"),
+            None => respond(StatusCode::NOT_FOUND, "Not Found"),
+        },
+        (Method::GET, ["tx_anchor"]) => {
+            let anchor = match chain.blocks.last() {
+                Some(block) => block.indep_hash.clone(),
+                None => Base64(sha_256(b"genesis").to_vec()).to_string(),
+            };
+            respond(StatusCode::OK, anchor)
+        }
+        (Method::GET, ["price", ..]) => respond(StatusCode::OK, "0"),
+        (Method::GET, ["info"]) => respond(
+            StatusCode::OK,
+            json!({"network": "harpocrates.local", "height": chain.height()}).to_string(),
+        ),
+        (Method::POST, ["graphql"]) => match graphql(&chain, &body) {
+            Ok(result) => respond(StatusCode::OK, result.to_string()),
+            Err(e) => respond(StatusCode::BAD_REQUEST, e.to_string()),
+        },
+        (Method::GET, [id]) | (Method::GET, [id, "data.json"]) => match chain.tx(id) {
+            Some(tx) => Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(Body::from(tx.data.clone()))
+                .unwrap(),
+            None => respond(StatusCode::NOT_FOUND, "Not Found"),
+        },
+        _ => respond(StatusCode::NOT_FOUND, "Not Found"),
+    };
+
+    Ok(response)
+}
+
+fn respond<T: Into<Body>>(status: StatusCode, body: T) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(body.into())
+        .unwrap()
+}
+
+fn decode(value: &Value) -> Result<Vec<u8>, Box<dyn Error>> {
+    match value.as_str() {
+        Some(s) => Ok(Base64::from_str(s)?.0),
+        None => Ok(vec![]),
+    }
+}
+
+// reads a signed transaction as posted by arloader, fields are base64url encoded
+fn parse_tx(body: &[u8]) -> Result<Tx, Box<dyn Error>> {
+    let tx: Value = serde_json::from_slice(body)?;
+
+    let id = tx["id"].as_str().ok_or("missing id")?.to_string();
+    let owner_key = tx["owner"].as_str().ok_or("missing owner")?.to_string();
+    let owner = Base64(sha_256(&decode(&tx["owner"])?).to_vec()).to_string();
+
+    let mut tags = vec![];
+    for tag in tx["tags"].as_array().unwrap_or(&vec![]) {
+        let name = String::from_utf8(decode(&tag["name"])?)?;
+        let value = String::from_utf8(decode(&tag["value"])?)?;
+        tags.push((name, value));
+    }
+
+    Ok(Tx {
+        id,
+        owner,
+        owner_key,
+        tags,
+        data: decode(&tx["data"])?,
+        height: None,
+    })
+}
+
+// answers a `transactions` query, only the tag filters and the `after` cursor are supported
+fn graphql(chain: &Chain, body: &[u8]) -> Result<Value, Box<dyn Error>> {
+    let request: Value = serde_json::from_slice(body)?;
+    let query = request["query"].as_str().ok_or("missing query")?;
+    let variables = &request["variables"];
+
+    let filters = tag_filters(query, variables);
+    let after = argument(query, "after", variables).and_then(|cursor| cursor.parse::<usize>().ok());
+
+    let edges: Vec<Value> = chain
+        .txs
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| after.map_or(true, |after| *i > after))
+        .filter(|(_, tx)| tx.height.is_some())
+        .filter(|(_, tx)| {
+            filters
+                .iter()
+                .all(|(name, values)| tx.tags.iter().any(|(n, v)| n == name && values.contains(v)))
+        })
+        .take(PAGE_SIZE + 1)
+        .map(|(i, tx)| {
+            let block = chain.block(tx.height.unwrap());
+            let tags: Vec<Value> = tx
+                .tags
+                .iter()
+                .map(|(name, value)| json!({"name": name, "value": value}))
+                .collect();
+            json!({
+                "cursor": i.to_string(),
+                "node": {
+                    "id": tx.id,
+                    "block": {"height": block.height, "timestamp": block.timestamp},
+                    "owner": {"address": tx.owner, "key": tx.owner_key},
+                    "tags": tags,
+                }
+            })
+        })
+        .collect();
+
+    let has_next_page = edges.len() > PAGE_SIZE;
+    let edges: Vec<Value> = edges.into_iter().take(PAGE_SIZE).collect();
+
+    Ok(json!({
+        "data": {
+            "transactions": {
+                "pageInfo": {"hasNextPage": has_next_page},
+                "edges": edges,
+            }
+        }
+    }))
+}
+
+// resolves a literal ("value") or a variable ($name) of the query
+fn resolve(token: &str, variables: &Value) -> Vec<String> {
+    let token = token.trim();
+    if let Some(name) = token.strip_prefix('$') {
+        match &variables[name] {
+            Value::String(s) => vec![s.clone()],
+            Value::Array(values) => values
+                .iter()
+                .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                .collect(),
+            _ => vec![],
+        }
+    } else {
+        vec![token.trim_matches('"').to_string()]
+    }
+}
+
+// value of a scalar argument of the query, such as `after: $follow_cursor`
+fn argument(query: &str, name: &str, variables: &Value) -> Option<String> {
+    let start = query.find(&format!("{}:", name))? + name.len() + 1;
+    let token = query[start..]
+        .split(|c: char| c.is_whitespace() || c == ',' || c == ')')
+        .find(|t| !t.is_empty())?;
+    resolve(token, variables)
+        .into_iter()
+        .find(|v| !v.is_empty())
+}
+
+// filters of `tags: [{ name: "App-Name", values: [$app] }, ...]`
+fn tag_filters(query: &str, variables: &Value) -> Vec<(String, Vec<String>)> {
+    let mut filters = vec![];
+
+    let start = match query.find("tags:") {
+        Some(start) => start,
+        None => return filters,
+    };
+
+    // the filter list ends on the bracket closing the first one
+    let mut depth = 0;
+    let mut list = "";
+    for (i, c) in query[start..].char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    list = &query[start..start + i];
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for filter in list.split("name:").skip(1) {
+        let name = filter.split('"').nth(1).unwrap_or_default().to_string();
+        let values = filter
+            .split_once("values:")
+            .and_then(|(_, v)| v.split_once('['))
+            .and_then(|(_, v)| v.split_once(']'))
+            .map(|(v, _)| {
+                v.split(',')
+                    .filter(|t| !t.trim().is_empty())
+                    .flat_map(|t| resolve(t, variables))
+                    .collect()
+            })
+            .unwrap_or_default();
+        filters.push((name, values));
+    }
+
+    filters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_tag_filters() {
+        let query = r#"query ($app: String!, $contract: String!) {
+            transactions(
+              after: $cursor
+              tags: [
+                { name: "App-Name", values: [$app] }
+                { name: "Contract", values: [$contract, "other"] }
+              ]
+            ) { edges { node { tags { name value } } } }
+          }"#;
+        let variables = json!({"app": "harpocrates-source", "contract": "abc", "cursor": "3"});
+
+        assert_eq!(
+            tag_filters(query, &variables),
+            vec![
+                (
+                    "App-Name".to_string(),
+                    vec!["harpocrates-source".to_string()]
+                ),
+                (
+                    "Contract".to_string(),
+                    vec!["abc".to_string(), "other".to_string()]
+                ),
+            ]
+        );
+        assert_eq!(argument(query, "after", &variables), Some("3".to_string()));
+    }
+}
@@ -0,0 +1,633 @@
+use crate::anonymous::{field_to_string, parse_field, MerklePath, VoterIdentity};
+use crate::arweave::sha_256;
+use crate::error::HarpocratesError;
+use ark_bn254::{Bn254, Fq, Fq2, Fr, G1Affine, G2Affine};
+use ark_circom::{read_zkey, CircomBuilder, CircomConfig, CircomReduction};
+use ark_ec::AffineRepr;
+use ark_ff::{BigInteger, PrimeField};
+use ark_groth16::{prepare_verifying_key, Groth16, Proof, ProvingKey, VerifyingKey};
+use ark_std::rand::thread_rng;
+use num_bigint::{BigInt, Sign};
+use serde_json::{json, Value};
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+use std::str::FromStr;
+
+// number of inputs of the vote_is_valid circuit (`signal input vote[10]`), polls can't have more options
+pub const VOTE_CIRCUIT_OPTIONS: usize = 10;
+
+// public signals of the vote_is_valid circuit: c, binding, contract and voter
+pub const VOTE_PUBLIC_INPUTS: usize = 4;
+
+// public signals of the anonymous_vote circuit: root, nullifier, binding and contract
+pub const ANONYMOUS_PUBLIC_INPUTS: usize = 4;
+
+/// Public input of the vote_is_valid circuit committing to the encrypted ballot and to the
+/// key it is encrypted to, so a proof can't be attached to another ciphertext.
+///
+/// It doesn't prove the ciphertext encrypts the proven vote, only that whoever made the
+/// proof had this ciphertext.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BallotBinding(Fr);
+
+impl BallotBinding {
+    /// `sha256(public_key || ciphertext)`, reduced to a field element
+    pub fn new(public_key: &[u8], ciphertext: &[u8]) -> Self {
+        let mut data = public_key.to_vec();
+        data.extend_from_slice(ciphertext);
+        BallotBinding(hash_to_field(&data))
+    }
+
+    /// Reads a binding written by `to_string`, when the ciphertext isn't known
+    pub fn parse(value: &str) -> Result<Self, HarpocratesError> {
+        Ok(BallotBinding(parse_field(value)?))
+    }
+}
+
+impl fmt::Display for BallotBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", field_to_string(&self.0))
+    }
+}
+
+/// What a vote proof is about: the public inputs of the vote_is_valid circuit, besides its output.
+///
+/// Along with the ballot, a proof is only valid for one contract and one voter, so it can't
+/// be replayed from another transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VoteStatement {
+    binding: BallotBinding,
+    contract: Fr,
+    voter: Fr,
+}
+
+impl VoteStatement {
+    pub fn new(binding: BallotBinding, contract_id: &str, eth_address: &str) -> Self {
+        VoteStatement {
+            binding,
+            contract: hash_to_field(contract_id.as_bytes()),
+            // addresses may or may not be checksummed
+            voter: hash_to_field(eth_address.to_lowercase().as_bytes()),
+        }
+    }
+
+    // in the order of the circuit, after the output `c`
+    fn inputs(&self) -> [(&'static str, Fr); 3] {
+        [
+            ("binding", self.binding.0),
+            ("contract", self.contract),
+            ("voter", self.voter),
+        ]
+    }
+}
+
+/// Public inputs of the anonymous_vote circuit, besides its outputs: the root of the tree the
+/// voter is part of, and the voter's nullifier.
+///
+/// Nothing in it tells who the voter is, the proof is only bound to the ballot and the contract.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AnonymousStatement {
+    binding: BallotBinding,
+    contract: Fr,
+}
+
+impl AnonymousStatement {
+    pub fn new(binding: BallotBinding, contract_id: &str) -> Self {
+        AnonymousStatement {
+            binding,
+            contract: hash_to_field(contract_id.as_bytes()),
+        }
+    }
+
+    // in the order of the circuit, after the outputs `root` and `nullifier`
+    fn inputs(&self) -> [(&'static str, Fr); 2] {
+        [("binding", self.binding.0), ("contract", self.contract)]
+    }
+}
+
+pub(crate) fn hash_to_field(data: &[u8]) -> Fr {
+    Fr::from_be_bytes_mod_order(&sha_256(data))
+}
+
+fn to_bigint(value: &Fr) -> BigInt {
+    BigInt::from_bytes_le(Sign::Plus, &value.into_bigint().to_bytes_le())
+}
+
+/// Proves ballots against the vote_is_valid circuit, without snarkjs or the C++ witness generator
+pub struct VoteProver {
+    circuit: CircomConfig<Bn254>,
+    params: ProvingKey<Bn254>,
+}
+
+impl VoteProver {
+    /// Loads the circuit (`vote_is_valid.wasm` and `vote_is_valid.r1cs`) and its snarkjs zkey
+    pub fn new(wasm: &Path, r1cs: &Path, zkey: &Path) -> Result<Self, HarpocratesError> {
+        let circuit = CircomConfig::<Bn254>::new(wasm, r1cs)
+            .map_err(|e| invalid(format!("cannot load circuit {}: {}", wasm.display(), e)))?;
+
+        let mut zkey_file = File::open(zkey).map_err(HarpocratesError::cache(zkey))?;
+        let (params, _) = read_zkey(&mut zkey_file)
+            .map_err(|e| invalid(format!("invalid zkey {}: {}", zkey.display(), e)))?;
+
+        Ok(VoteProver { circuit, params })
+    }
+
+    /// Computes the witness for `vote` and proves it, returns the contents of
+    /// snarkjs' `proof.json` and `public.json`
+    pub fn prove(
+        &self,
+        vote: &[i64],
+        statement: &VoteStatement,
+    ) -> Result<(String, String), HarpocratesError> {
+        let inputs = statement
+            .inputs()
+            .iter()
+            .map(|(name, value)| (*name, vec![to_bigint(value)]))
+            .collect();
+        self.prove_inputs(vote, inputs)
+    }
+
+    /// Same for the anonymous_vote circuit, as the member of the group `path` was made for
+    pub fn prove_anonymous(
+        &self,
+        vote: &[i64],
+        statement: &AnonymousStatement,
+        identity: &VoterIdentity,
+        path: &MerklePath,
+    ) -> Result<(String, String), HarpocratesError> {
+        let mut inputs: Vec<(&str, Vec<BigInt>)> = statement
+            .inputs()
+            .iter()
+            .map(|(name, value)| (*name, vec![to_bigint(value)]))
+            .collect();
+        inputs.push(("identity_secret", vec![to_bigint(&identity.secret)]));
+        inputs.push((
+            "path_elements",
+            path.elements.iter().map(to_bigint).collect(),
+        ));
+        inputs.push((
+            "path_indices",
+            path.indices.iter().map(|i| BigInt::from(*i)).collect(),
+        ));
+        self.prove_inputs(vote, inputs)
+    }
+
+    fn prove_inputs(
+        &self,
+        vote: &[i64],
+        inputs: Vec<(&str, Vec<BigInt>)>,
+    ) -> Result<(String, String), HarpocratesError> {
+        let mut builder = CircomBuilder::new(self.circuit.clone());
+        for v in vote {
+            builder.push_input("vote", *v);
+        }
+        for (name, values) in inputs {
+            for value in values {
+                builder.push_input(name, value);
+            }
+        }
+
+        let circuit = builder
+            .build()
+            .map_err(|e| invalid(format!("cannot compute the witness: {}", e)))?;
+        let public = circuit
+            .get_public_inputs()
+            .ok_or_else(|| invalid("the witness has no public inputs"))?;
+
+        // the zkey comes from snarkjs, so the proof has to use its R1CS to QAP reduction
+        let proof = Groth16::<Bn254, CircomReduction>::create_random_proof_with_reduction(
+            circuit,
+            &self.params,
+            &mut thread_rng(),
+        )
+        .map_err(|e| invalid(format!("cannot create the proof: {}", e)))?;
+
+        let public: Vec<String> = public.iter().map(|i| i.into_bigint().to_string()).collect();
+        Ok((proof_json(&proof).to_string(), json!(public).to_string()))
+    }
+}
+
+/// Outcome of checking a proof that could be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verification {
+    Valid,
+    Invalid(String),
+}
+
+impl Verification {
+    pub fn is_valid(&self) -> bool {
+        *self == Verification::Valid
+    }
+}
+
+/// Verifies a BN254 Groth16 proof in the snarkjs formats (`verification_key.json`,
+/// `proof.json` and `public.json`), without calling out to snarkjs.
+///
+/// Files that can't be parsed are an error, a proof that doesn't check out is `Invalid`.
+pub fn verify_proof(
+    verification_key: &str,
+    proof: &str,
+    public: &str,
+) -> Result<Verification, HarpocratesError> {
+    let vk = parse_verification_key(&serde_json::from_str(verification_key)?)?;
+    let proof = parse_proof(&serde_json::from_str(proof)?)?;
+    let public = parse_public(&serde_json::from_str(public)?)?;
+
+    // gamma_abc holds one point per public input, plus one for the constant
+    if public.len() + 1 != vk.gamma_abc_g1.len() {
+        return Ok(Verification::Invalid(format!(
+            "expected {} public inputs, got {}",
+            vk.gamma_abc_g1.len() - 1,
+            public.len()
+        )));
+    }
+
+    let pvk = prepare_verifying_key(&vk);
+    match Groth16::<Bn254>::verify_proof(&pvk, &proof, &public) {
+        Ok(true) => Ok(Verification::Valid),
+        Ok(false) => Ok(Verification::Invalid("pairing check failed".to_string())),
+        Err(e) => Ok(Verification::Invalid(e.to_string())),
+    }
+}
+
+/// Checks a verification key was exported for a circuit with `public_inputs` public signals.
+///
+/// Keys left over from an older version of the circuit would reject every proof.
+pub fn check_verification_key(
+    verification_key: &str,
+    public_inputs: usize,
+) -> Result<(), HarpocratesError> {
+    let vk = parse_verification_key(&serde_json::from_str(verification_key)?)?;
+    let found = vk.gamma_abc_g1.len().saturating_sub(1);
+    if found != public_inputs {
+        return Err(invalid(format!(
+            "the verification key has {} public inputs instead of {}, run make to rebuild the circuit and its keys",
+            found, public_inputs
+        )));
+    }
+    Ok(())
+}
+
+/// Verifies the proof of a vote, which must be about `statement`
+pub fn verify_vote(
+    verification_key: &str,
+    proof: &str,
+    public: &str,
+    statement: &VoteStatement,
+) -> Result<Verification, HarpocratesError> {
+    let inputs = parse_public(&serde_json::from_str(public)?)?;
+
+    // snarkjs lists the outputs first, the public signals are [c, binding, contract, voter]
+    let after_outputs = inputs.get(1..).unwrap_or_default();
+    if let Some(invalid) = check_inputs(after_outputs, &statement.inputs()) {
+        return Ok(invalid);
+    }
+
+    verify_proof(verification_key, proof, public)
+}
+
+/// Verifies the proof of an anonymous vote, which must be about `statement` and made by a
+/// member of the group with this `root`
+pub fn verify_anonymous_vote(
+    verification_key: &str,
+    proof: &str,
+    public: &str,
+    statement: &AnonymousStatement,
+    root: &Fr,
+) -> Result<Verification, HarpocratesError> {
+    let inputs = parse_public(&serde_json::from_str(public)?)?;
+
+    // the public signals are [root, nullifier, binding, contract]
+    if let Some(invalid) = check_inputs(&inputs, &[("root", *root)]) {
+        return Ok(invalid);
+    }
+    let after_outputs = inputs.get(2..).unwrap_or_default();
+    if let Some(invalid) = check_inputs(after_outputs, &statement.inputs()) {
+        return Ok(invalid);
+    }
+
+    verify_proof(verification_key, proof, public)
+}
+
+/// Nullifier of the voter who made an anonymous vote proof, only meaningful once it is verified
+pub fn anonymous_nullifier(public: &str) -> Result<String, HarpocratesError> {
+    let inputs = parse_public(&serde_json::from_str(public)?)?;
+    inputs
+        .get(1)
+        .map(field_to_string)
+        .ok_or_else(|| invalid("the proof has no nullifier"))
+}
+
+// compares the public inputs with the expected ones, in order
+fn check_inputs(inputs: &[Fr], expected: &[(&str, Fr)]) -> Option<Verification> {
+    for (i, (name, expected)) in expected.iter().enumerate() {
+        match inputs.get(i) {
+            Some(input) if input == expected => {}
+            Some(_) => {
+                return Some(Verification::Invalid(format!(
+                    "the proof was made for another {}",
+                    name
+                )))
+            }
+            None => {
+                return Some(Verification::Invalid(format!(
+                    "the proof has no {} public input",
+                    name
+                )))
+            }
+        }
+    }
+    None
+}
+
+fn parse_verification_key(vk: &Value) -> Result<VerifyingKey<Bn254>, HarpocratesError> {
+    if vk["protocol"] != "groth16" || vk["curve"] != "bn128" {
+        return Err(invalid(format!(
+            "unsupported verification key {}/{}",
+            vk["protocol"], vk["curve"]
+        )));
+    }
+
+    let gamma_abc_g1 = vk["IC"]
+        .as_array()
+        .ok_or_else(|| invalid("missing IC in verification key"))?
+        .iter()
+        .map(g1)
+        .collect::<Result<_, _>>()?;
+
+    Ok(VerifyingKey {
+        alpha_g1: g1(&vk["vk_alpha_1"])?,
+        beta_g2: g2(&vk["vk_beta_2"])?,
+        gamma_g2: g2(&vk["vk_gamma_2"])?,
+        delta_g2: g2(&vk["vk_delta_2"])?,
+        gamma_abc_g1,
+    })
+}
+
+fn parse_proof(proof: &Value) -> Result<Proof<Bn254>, HarpocratesError> {
+    if proof["protocol"] != "groth16" {
+        return Err(invalid(format!("unsupported proof {}", proof["protocol"])));
+    }
+
+    Ok(Proof {
+        a: g1(&proof["pi_a"])?,
+        b: g2(&proof["pi_b"])?,
+        c: g1(&proof["pi_c"])?,
+    })
+}
+
+fn parse_public(public: &Value) -> Result<Vec<Fr>, HarpocratesError> {
+    public
+        .as_array()
+        .ok_or_else(|| invalid("public inputs are not an array"))?
+        .iter()
+        .map(|input| {
+            let input = input
+                .as_str()
+                .ok_or_else(|| invalid(format!("public input {} is not a string", input)))?;
+            Fr::from_str(input).map_err(|_| invalid(format!("invalid public input {}", input)))
+        })
+        .collect()
+}
+
+fn fq(value: &Value) -> Result<Fq, HarpocratesError> {
+    value
+        .as_str()
+        .and_then(|v| Fq::from_str(v).ok())
+        .ok_or_else(|| invalid(format!("invalid field element {}", value)))
+}
+
+fn fq2(value: &Value) -> Result<Fq2, HarpocratesError> {
+    Ok(Fq2::new(fq(&value[0])?, fq(&value[1])?))
+}
+
+// snarkjs writes points as projective [x, y, z] with z = 1, or z = 0 for infinity
+fn g1(point: &Value) -> Result<G1Affine, HarpocratesError> {
+    if fq(&point[2])? == Fq::from(0u64) {
+        return Ok(G1Affine::zero());
+    }
+    let p = G1Affine::new_unchecked(fq(&point[0])?, fq(&point[1])?);
+    if !p.is_on_curve() || !p.is_in_correct_subgroup_assuming_on_curve() {
+        return Err(invalid(format!("point {} is not on the curve", point)));
+    }
+    Ok(p)
+}
+
+fn g2(point: &Value) -> Result<G2Affine, HarpocratesError> {
+    if fq2(&point[2])? == Fq2::from(0u64) {
+        return Ok(G2Affine::zero());
+    }
+    let p = G2Affine::new_unchecked(fq2(&point[0])?, fq2(&point[1])?);
+    if !p.is_on_curve() || !p.is_in_correct_subgroup_assuming_on_curve() {
+        return Err(invalid(format!("point {} is not on the curve", point)));
+    }
+    Ok(p)
+}
+
+// same layout as snarkjs' proof.json
+fn proof_json(proof: &Proof<Bn254>) -> Value {
+    json!({
+        "pi_a": g1_json(&proof.a),
+        "pi_b": g2_json(&proof.b),
+        "pi_c": g1_json(&proof.c),
+        "protocol": "groth16",
+        "curve": "bn128",
+    })
+}
+
+fn fq_json(value: &Fq) -> Value {
+    json!(value.into_bigint().to_string())
+}
+
+fn g1_json(point: &G1Affine) -> Value {
+    if point.infinity {
+        return json!(["0", "1", "0"]);
+    }
+    json!([fq_json(&point.x), fq_json(&point.y), "1"])
+}
+
+fn g2_json(point: &G2Affine) -> Value {
+    if point.infinity {
+        return json!([["0", "0"], ["1", "0"], ["0", "0"]]);
+    }
+    json!([
+        [fq_json(&point.x.c0), fq_json(&point.x.c1)],
+        [fq_json(&point.y.c0), fq_json(&point.y.c1)],
+        ["1", "0"]
+    ])
+}
+
+fn invalid<S: Into<String>>(message: S) -> HarpocratesError {
+    HarpocratesError::Proof(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::vote_circuit_is_built;
+
+    const VERIFICATION_KEY: &str = include_str!("../circom/verification_key.json");
+    const PROOF: &str = include_str!("../circom/proof.json");
+    const PUBLIC: &str = include_str!("../circom/public.json");
+
+    // first hardhat dev account
+    const VOTER: &str = "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266";
+
+    #[test]
+    fn it_verifies_a_snarkjs_proof() -> Result<(), HarpocratesError> {
+        assert_eq!(
+            verify_proof(VERIFICATION_KEY, PROOF, PUBLIC)?,
+            Verification::Valid
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_has_keys_for_the_current_circuit() -> Result<(), HarpocratesError> {
+        if !vote_circuit_is_built() {
+            return Ok(());
+        }
+        check_verification_key(VERIFICATION_KEY, VOTE_PUBLIC_INPUTS)?;
+        assert!(check_verification_key(VERIFICATION_KEY, VOTE_PUBLIC_INPUTS + 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_a_wrong_public_input() -> Result<(), HarpocratesError> {
+        let result = verify_proof(VERIFICATION_KEY, PROOF, r#"["0"]"#)?;
+        assert!(!result.is_valid());
+
+        let result = verify_proof(VERIFICATION_KEY, PROOF, r#"["1", "1"]"#)?;
+        assert!(!result.is_valid());
+        Ok(())
+    }
+
+    fn vote_prover() -> Result<VoteProver, HarpocratesError> {
+        VoteProver::new(
+            Path::new("./circom/vote_is_valid_js/vote_is_valid.wasm"),
+            Path::new("./circom/vote_is_valid.r1cs"),
+            Path::new("./circom/vote_is_valid_0001.zkey"),
+        )
+    }
+
+    #[test]
+    fn it_proves_a_ballot_in_process() -> Result<(), HarpocratesError> {
+        if !vote_circuit_is_built() {
+            return Ok(());
+        }
+        let mut ballot = vec![0; VOTE_CIRCUIT_OPTIONS];
+        ballot[2] = 1;
+        let binding = BallotBinding::new(b"pk", b"ciphertext");
+        let statement = VoteStatement::new(binding, "contract", VOTER);
+
+        let (proof, public) = vote_prover()?.prove(&ballot, &statement)?;
+
+        assert_eq!(
+            verify_vote(VERIFICATION_KEY, &proof, &public, &statement)?,
+            Verification::Valid
+        );
+        // checksummed or not, it is the same address
+        let checksummed = VoteStatement::new(binding, "contract", &VOTER.to_uppercase());
+        assert!(verify_vote(VERIFICATION_KEY, &proof, &public, &checksummed)?.is_valid());
+
+        // the same proof attached to another ciphertext, sent to another contract or by someone else
+        for other in [
+            VoteStatement::new(
+                BallotBinding::new(b"pk", b"another ciphertext"),
+                "contract",
+                VOTER,
+            ),
+            VoteStatement::new(binding, "another contract", VOTER),
+            VoteStatement::new(
+                binding,
+                "contract",
+                "0x70997970c51812dc3a010c7d01b50e0d17dc79c8",
+            ),
+        ] {
+            assert!(!verify_vote(VERIFICATION_KEY, &proof, &public, &other)?.is_valid());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn it_cannot_prove_a_double_vote() -> Result<(), HarpocratesError> {
+        if !vote_circuit_is_built() {
+            return Ok(());
+        }
+        let mut ballot = vec![0; VOTE_CIRCUIT_OPTIONS];
+        ballot[2] = 1;
+        ballot[3] = 1;
+
+        // either the witness can't be computed or the proof doesn't verify
+        let statement =
+            VoteStatement::new(BallotBinding::new(b"pk", b"ciphertext"), "contract", VOTER);
+        if let Ok((proof, public)) = vote_prover()?.prove(&ballot, &statement) {
+            assert!(!verify_vote(VERIFICATION_KEY, &proof, &public, &statement)?.is_valid());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_a_vote_for_another_contract() -> Result<(), HarpocratesError> {
+        let binding = BallotBinding::new(b"pk", b"ciphertext");
+        let statement = VoteStatement::new(binding, "contract A", VOTER);
+        let public = json!([
+            "1",
+            to_bigint(&binding.0).to_string(),
+            to_bigint(&hash_to_field(b"contract A")).to_string(),
+            to_bigint(&hash_to_field(VOTER.as_bytes())).to_string(),
+        ])
+        .to_string();
+        // everything but the contract matches the proof
+        let inputs = parse_public(&serde_json::from_str(&public)?)?;
+        assert_eq!(check_inputs(&inputs[1..], &statement.inputs()), None);
+
+        // the same proof, replayed in a vote for contract B
+        let other_contract = VoteStatement::new(binding, "contract B", VOTER);
+        assert_eq!(
+            verify_vote(VERIFICATION_KEY, PROOF, &public, &other_contract)?,
+            Verification::Invalid("the proof was made for another contract".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_checks_the_outputs_of_anonymous_votes() -> Result<(), HarpocratesError> {
+        let binding = BallotBinding::new(b"pk", b"ciphertext");
+        let statement = AnonymousStatement::new(binding, "contract");
+        let public = json!([
+            "1",
+            "42",
+            to_bigint(&binding.0).to_string(),
+            to_bigint(&hash_to_field(b"contract")).to_string(),
+        ])
+        .to_string();
+
+        assert_eq!(anonymous_nullifier(&public)?, "42");
+
+        let other_group = Fr::from(2u64);
+        assert_eq!(
+            verify_anonymous_vote(VERIFICATION_KEY, PROOF, &public, &statement, &other_group)?,
+            Verification::Invalid("the proof was made for another root".to_string())
+        );
+        let other_contract = AnonymousStatement::new(binding, "another contract");
+        assert_eq!(
+            verify_anonymous_vote(
+                VERIFICATION_KEY,
+                PROOF,
+                &public,
+                &other_contract,
+                &Fr::from(1u64)
+            )?,
+            Verification::Invalid("the proof was made for another contract".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_errors_on_malformed_proofs() {
+        let err = verify_proof(VERIFICATION_KEY, r#"{"protocol": "groth16"}"#, PUBLIC);
+        assert!(matches!(err, Err(HarpocratesError::Proof(_))));
+    }
+}
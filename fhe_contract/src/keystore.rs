@@ -0,0 +1,195 @@
+use crate::arweave::{decode_hex, encode_hex};
+use crate::error::HarpocratesError;
+use argon2::{Algorithm, Argon2, Params, Version};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+// read instead of prompting, for scripts
+pub const PASSPHRASE_VAR: &str = "HARPOCRATES_PASSPHRASE";
+
+const SALT_LEN: usize = 16;
+
+/// A secret encrypted with ChaCha20-Poly1305, under a key derived from a passphrase with Argon2id.
+///
+/// Everything needed to derive the key again but the passphrase is stored along with it,
+/// so the cost can be raised without breaking the files already written.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Sealed {
+    /// Memory cost in KiB
+    pub m_cost: u32,
+    /// Number of passes
+    pub t_cost: u32,
+    /// Degree of parallelism
+    pub p_cost: u32,
+    pub salt: String,
+    pub nonce: String,
+    /// Encrypted secret and its tag
+    pub ciphertext: String,
+}
+
+impl Sealed {
+    /// Encrypts `secret` with the recommended Argon2id parameters
+    pub fn seal(secret: &[u8], passphrase: &str) -> Result<Self, HarpocratesError> {
+        let rng = SystemRandom::new();
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        rng.fill(&mut salt)
+            .and_then(|_| rng.fill(&mut nonce))
+            .map_err(|_| HarpocratesError::Key("no randomness available".to_string()))?;
+
+        let params = Params::default();
+        let key = derive_key(
+            passphrase,
+            &salt,
+            params.m_cost(),
+            params.t_cost(),
+            params.p_cost(),
+        )?;
+
+        let mut in_out = Zeroizing::new(secret.to_vec());
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::empty(),
+            &mut *in_out,
+        )
+        .map_err(|_| HarpocratesError::Key("cannot encrypt the secret".to_string()))?;
+
+        Ok(Sealed {
+            m_cost: params.m_cost(),
+            t_cost: params.t_cost(),
+            p_cost: params.p_cost(),
+            salt: encode_hex(&salt),
+            nonce: encode_hex(&nonce),
+            ciphertext: encode_hex(&in_out),
+        })
+    }
+
+    /// Decrypts the secret, it is wiped from memory once dropped
+    pub fn open(&self, passphrase: &str) -> Result<Zeroizing<Vec<u8>>, HarpocratesError> {
+        let salt = decode_hex(&self.salt)?;
+        let nonce = Nonce::try_assume_unique_for_key(&decode_hex(&self.nonce)?)
+            .map_err(|_| HarpocratesError::Serialization("invalid nonce".to_string()))?;
+        let key = derive_key(passphrase, &salt, self.m_cost, self.t_cost, self.p_cost)?;
+
+        let mut in_out = Zeroizing::new(decode_hex(&self.ciphertext)?);
+        let len = key
+            .open_in_place(nonce, Aad::empty(), &mut *in_out)
+            .map_err(|_| {
+                HarpocratesError::Key("wrong passphrase, or the file was modified".to_string())
+            })?
+            .len();
+        in_out.truncate(len);
+        Ok(in_out)
+    }
+
+    pub fn read(path: &Path) -> Result<Self, HarpocratesError> {
+        let raw = fs::read_to_string(path).map_err(HarpocratesError::cache(path))?;
+        serde_json::from_str(&raw).map_err(|e| {
+            HarpocratesError::Key(format!("{} is not encrypted: {}", path.display(), e))
+        })
+    }
+
+    /// Whether `path` holds a sealed secret rather than a plaintext one
+    pub fn is_sealed(path: &Path) -> bool {
+        Sealed::read(path).is_ok()
+    }
+
+    /// Writes the sealed secret, only readable by its owner
+    pub fn write(&self, path: &Path) -> Result<(), HarpocratesError> {
+        write_private(path, serde_json::to_string(self)?.as_bytes())
+    }
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+) -> Result<LessSafeKey, HarpocratesError> {
+    let params = Params::new(m_cost, t_cost, p_cost, Some(CHACHA20_POLY1305.key_len()))
+        .map_err(|e| HarpocratesError::Key(format!("invalid key derivation: {}", e)))?;
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut *key)
+        .map_err(|e| HarpocratesError::Key(format!("cannot derive the key: {}", e)))?;
+
+    let key = UnboundKey::new(&CHACHA20_POLY1305, &*key)
+        .map_err(|_| HarpocratesError::Key("invalid key length".to_string()))?;
+    Ok(LessSafeKey::new(key))
+}
+
+/// Reads the passphrase from `HARPOCRATES_PASSPHRASE`, or asks for it.
+/// A new passphrase is asked twice
+pub fn passphrase(prompt: &str, new: bool) -> Result<Zeroizing<String>, HarpocratesError> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_VAR) {
+        return Ok(Zeroizing::new(passphrase));
+    }
+
+    let ask = |prompt: &str| {
+        rpassword::prompt_password(prompt)
+            .map(Zeroizing::new)
+            .map_err(|e| HarpocratesError::Key(format!("cannot read the passphrase: {}", e)))
+    };
+    let passphrase = ask(prompt)?;
+    if new {
+        if passphrase.is_empty() {
+            return Err(HarpocratesError::Key(
+                "the passphrase can't be empty".to_string(),
+            ));
+        }
+        if *ask("Repeat the passphrase: ")? != *passphrase {
+            return Err(HarpocratesError::Key(
+                "the passphrases don't match".to_string(),
+            ));
+        }
+    }
+    Ok(passphrase)
+}
+
+/// Writes a file only its owner can read, replacing it if it exists
+pub fn write_private(path: &Path, data: &[u8]) -> Result<(), HarpocratesError> {
+    // the mode only applies on creation
+    if path.exists() {
+        fs::remove_file(path).map_err(HarpocratesError::cache(path))?;
+    }
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(data))
+        .map_err(HarpocratesError::cache(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_opens_a_sealed_secret_with_its_passphrase() -> Result<(), HarpocratesError> {
+        let sealed = Sealed::seal(b"the secret key", "correct horse")?;
+        assert!(!sealed.ciphertext.contains(&encode_hex(b"the secret key")));
+
+        assert_eq!(&*sealed.open("correct horse")?, b"the secret key");
+        assert!(sealed.open("battery staple").is_err());
+
+        // tampering with the ciphertext or the parameters is caught by the tag
+        let mut ciphertext = decode_hex(&sealed.ciphertext)?;
+        ciphertext[0] ^= 1;
+        let mut tampered = sealed.clone();
+        tampered.ciphertext = encode_hex(&ciphertext);
+        assert!(tampered.open("correct horse").is_err());
+        let mut tampered = sealed;
+        tampered.t_cost += 1;
+        assert!(tampered.open("correct horse").is_err());
+        Ok(())
+    }
+}
@@ -8,8 +8,39 @@ pub mod cli;
 
 pub mod arweave;
 
-pub mod snarkjs;
+pub mod store;
+
+pub mod transaction;
+
+pub mod action;
+
+pub mod replay;
+
+pub mod allowlist;
+
+pub mod anonymous;
+
+pub mod queue;
+
+pub mod poll;
+
+pub mod tally;
+
+pub mod keystore;
+
+pub mod profile;
+
+pub mod gateway;
+
+pub mod groth16;
 
 pub mod ascii;
 
 pub mod ethereum;
+
+pub mod error;
+
+pub mod config;
+
+#[cfg(test)]
+mod testing;
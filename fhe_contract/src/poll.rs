@@ -0,0 +1,133 @@
+use crate::error::HarpocratesError;
+use crate::transaction::Block;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// A point of the chain a poll opens or closes at, set at deploy time
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PollTime {
+    Height(u64),
+    /// Unix timestamp of the block, in seconds
+    Timestamp(u64),
+}
+
+impl PollTime {
+    // whether the chain got to this point by `block`
+    fn reached(&self, block: &Block) -> bool {
+        match self {
+            PollTime::Height(height) => block.height >= *height,
+            PollTime::Timestamp(timestamp) => block.timestamp >= *timestamp,
+        }
+    }
+}
+
+/// Reads `height:<N>` or `timestamp:<UNIX>`
+impl FromStr for PollTime {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parse = |n: &str| {
+            n.parse::<u64>()
+                .map_err(|_| format!("{:?} is not a block height or a timestamp", n))
+        };
+        match value.split_once(':') {
+            Some(("height", n)) => parse(n).map(PollTime::Height),
+            Some(("timestamp", n)) => parse(n).map(PollTime::Timestamp),
+            _ => Err(format!(
+                "expected height:<N> or timestamp:<UNIX>, got {:?}",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PollTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PollTime::Height(height) => write!(f, "height {}", height),
+            PollTime::Timestamp(timestamp) => write!(f, "timestamp {}", timestamp),
+        }
+    }
+}
+
+/// When a poll takes votes: from the first block reaching `opens`, up to the first block
+/// reaching `closes` excluded. Only the blocks count, `Unix-Time` tags are chosen by the voters
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PollWindow {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opens: Option<PollTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closes: Option<PollTime>,
+}
+
+impl PollWindow {
+    pub fn new(
+        opens: Option<PollTime>,
+        closes: Option<PollTime>,
+    ) -> Result<Self, HarpocratesError> {
+        if let (Some(PollTime::Height(opens)), Some(PollTime::Height(closes)))
+        | (Some(PollTime::Timestamp(opens)), Some(PollTime::Timestamp(closes))) = (opens, closes)
+        {
+            if opens >= closes {
+                return Err(HarpocratesError::Contract(
+                    "the poll has to open before it closes".to_string(),
+                ));
+            }
+        }
+        Ok(PollWindow { opens, closes })
+    }
+
+    /// Checks a vote mined in `block` was sent while the poll was open
+    pub fn check(&self, block: Option<&Block>) -> Result<(), HarpocratesError> {
+        if self.opens.is_none() && self.closes.is_none() {
+            return Ok(());
+        }
+        let block = block.ok_or_else(|| {
+            HarpocratesError::Contract("not mined yet, it can't be placed in the poll".to_string())
+        })?;
+
+        match (self.opens, self.closes) {
+            (Some(opens), _) if !opens.reached(block) => Err(HarpocratesError::Contract(format!(
+                "sent before the poll opened at {}",
+                opens
+            ))),
+            (_, Some(closes)) if closes.reached(block) => Err(HarpocratesError::Contract(format!(
+                "sent after the poll closed at {}",
+                closes
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(height: u64, timestamp: u64) -> Option<Block> {
+        Some(Block { height, timestamp })
+    }
+
+    #[test]
+    fn it_only_counts_votes_in_the_window() -> Result<(), HarpocratesError> {
+        let window = PollWindow::new(Some(PollTime::Height(10)), Some(PollTime::Timestamp(2000)))?;
+
+        window.check(block(10, 1000).as_ref())?;
+        window.check(block(20, 1999).as_ref())?;
+        assert!(window.check(block(9, 1000).as_ref()).is_err());
+        assert!(window.check(block(20, 2000).as_ref()).is_err());
+        assert!(window.check(None).is_err());
+
+        // without a window, even pending votes count
+        PollWindow::default().check(None)?;
+
+        assert!(PollWindow::new(Some(PollTime::Height(5)), Some(PollTime::Height(5))).is_err());
+        assert_eq!("height:10".parse(), Ok(PollTime::Height(10)));
+        assert_eq!("timestamp:2000".parse(), Ok(PollTime::Timestamp(2000)));
+        assert!("block:5".parse::<PollTime>().is_err());
+        assert!("height:soon".parse::<PollTime>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+use crate::error::HarpocratesError;
+use crate::keystore::{write_private, Sealed};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+// what a profile keeps, moved out of the cache root by `adopt_legacy`
+const PROFILE_FILES: [&str; 3] = ["pk.json", "sk.json", "identity.json"];
+
+/// What `keys export` writes: the public key, and the secret key still encrypted
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ExportedKeys {
+    pub pk: String,
+    pub sk: Sealed,
+}
+
+/// A profile as shown by `keys list`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProfileInfo {
+    pub name: String,
+    /// Whether the secret key is encrypted, `None` if the profile has no FHE keys (e.g. it only
+    /// has an identity to vote anonymously)
+    pub sealed: Option<bool>,
+    /// Contracts this profile is the admin of
+    pub administers: Vec<String>,
+}
+
+/// Named key pairs, so that one checkout can administer several polls and vote on others.
+///
+/// A profile keeps its FHE keys and its anonymous identity in `<root>/<name>/`, and
+/// `<root>/admins.json` records which profile deployed (and so decrypts) each contract.
+pub struct Profiles {
+    root: PathBuf,
+}
+
+impl Profiles {
+    pub fn new(root: PathBuf) -> Self {
+        Profiles { root }
+    }
+
+    /// Where a profile keeps `file`, the name can't leave the profiles directory
+    pub fn file(&self, name: &str, file: &str) -> Result<PathBuf, HarpocratesError> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(HarpocratesError::Key(format!(
+                "invalid profile name {:?}, use letters, digits, - and _",
+                name
+            )));
+        }
+        Ok(self.root.join(name).join(file))
+    }
+
+    /// Creates the directory of a profile, only readable by its owner
+    pub fn create(&self, name: &str) -> Result<PathBuf, HarpocratesError> {
+        let dir = self.file(name, "")?;
+        fs::create_dir_all(&dir).map_err(HarpocratesError::cache(&dir))?;
+        set_private(&dir)?;
+        Ok(dir)
+    }
+
+    pub fn exists(&self, name: &str) -> Result<bool, HarpocratesError> {
+        Ok(self.file(name, "sk.json")?.exists())
+    }
+
+    pub fn list(&self) -> Result<Vec<ProfileInfo>, HarpocratesError> {
+        if !self.root.exists() {
+            return Ok(vec![]);
+        }
+        let admins = self.admins()?;
+        let mut names = fs::read_dir(&self.root)
+            .map_err(HarpocratesError::cache(&self.root))?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect::<Vec<_>>();
+        names.sort();
+
+        names
+            .into_iter()
+            .map(|name| {
+                let sk_path = self.file(&name, "sk.json")?;
+                Ok(ProfileInfo {
+                    sealed: sk_path.exists().then(|| Sealed::is_sealed(&sk_path)),
+                    administers: admins
+                        .iter()
+                        .filter(|(_, admin)| **admin == name)
+                        .map(|(contract_id, _)| contract_id.clone())
+                        .collect(),
+                    name,
+                })
+            })
+            .collect()
+    }
+
+    /// The profile that deployed the contract from here, if any
+    pub fn admin_of(&self, contract_id: &str) -> Result<Option<String>, HarpocratesError> {
+        Ok(self.admins()?.remove(contract_id))
+    }
+
+    pub fn set_admin(&self, contract_id: &str, name: &str) -> Result<(), HarpocratesError> {
+        if !self.exists(name)? {
+            return Err(HarpocratesError::Key(format!(
+                "no keys for profile {}",
+                name
+            )));
+        }
+        let mut admins = self.admins()?;
+        admins.insert(contract_id.to_string(), name.to_string());
+        self.write_admins(&admins)
+    }
+
+    pub fn export(&self, name: &str) -> Result<ExportedKeys, HarpocratesError> {
+        let pk_path = self.file(name, "pk.json")?;
+        Ok(ExportedKeys {
+            pk: fs::read_to_string(&pk_path).map_err(HarpocratesError::cache(&pk_path))?,
+            sk: Sealed::read(&self.file(name, "sk.json")?)?,
+        })
+    }
+
+    /// Saves exported keys as a new profile, an existing one is never replaced
+    pub fn import(&self, name: &str, keys: &ExportedKeys) -> Result<(), HarpocratesError> {
+        if self.exists(name)? {
+            return Err(HarpocratesError::Key(format!(
+                "profile {} already has keys",
+                name
+            )));
+        }
+        self.create(name)?;
+        let pk_path = self.file(name, "pk.json")?;
+        fs::write(&pk_path, &keys.pk).map_err(HarpocratesError::cache(&pk_path))?;
+        keys.sk.write(&self.file(name, "sk.json")?)
+    }
+
+    /// Removes a profile, along with the contracts it is the admin of if `force` is set.
+    /// Returns these contracts
+    pub fn delete(&self, name: &str, force: bool) -> Result<Vec<String>, HarpocratesError> {
+        let dir = self.file(name, "")?;
+        if !dir.exists() {
+            return Err(HarpocratesError::Key(format!("no profile {}", name)));
+        }
+
+        // without its keys, nobody can decrypt these polls anymore
+        let mut admins = self.admins()?;
+        let administers = admins
+            .iter()
+            .filter(|(_, admin)| *admin == name)
+            .map(|(contract_id, _)| contract_id.clone())
+            .collect::<Vec<_>>();
+        if !administers.is_empty() && !force {
+            return Err(HarpocratesError::Key(format!(
+                "profile {} is the admin of {}, export it first or pass --force",
+                name,
+                administers.join(", ")
+            )));
+        }
+
+        admins.retain(|_, admin| *admin != name);
+        self.write_admins(&admins)?;
+        fs::remove_dir_all(&dir).map_err(HarpocratesError::cache(&dir))?;
+        Ok(administers)
+    }
+
+    /// Moves the keys written in `cache_dir` before there were profiles to the default profile
+    pub fn adopt_legacy(&self, cache_dir: &Path) -> Result<bool, HarpocratesError> {
+        if !cache_dir.join("sk.json").exists() || self.exists(DEFAULT_PROFILE)? {
+            return Ok(false);
+        }
+        self.create(DEFAULT_PROFILE)?;
+        for name in PROFILE_FILES {
+            let from = cache_dir.join(name);
+            if from.exists() {
+                fs::rename(&from, self.file(DEFAULT_PROFILE, name)?)
+                    .map_err(HarpocratesError::cache(&from))?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn admins(&self) -> Result<BTreeMap<String, String>, HarpocratesError> {
+        let path = self.root.join("admins.json");
+        if !path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let content = fs::read_to_string(&path).map_err(HarpocratesError::cache(&path))?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    fn write_admins(&self, admins: &BTreeMap<String, String>) -> Result<(), HarpocratesError> {
+        fs::create_dir_all(&self.root).map_err(HarpocratesError::cache(&self.root))?;
+        write_private(
+            &self.root.join("admins.json"),
+            serde_json::to_string(admins)?.as_bytes(),
+        )
+    }
+}
+
+fn set_private(dir: &Path) -> Result<(), HarpocratesError> {
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+        .map_err(HarpocratesError::cache(dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    #[test]
+    fn it_keeps_profiles_and_their_contracts_apart() -> Result<(), HarpocratesError> {
+        let cache_dir = temp_dir("profile");
+        fs::create_dir_all(&cache_dir).map_err(HarpocratesError::cache(&cache_dir))?;
+        let profiles = Profiles::new(cache_dir.join("profiles"));
+
+        // keys written before profiles become the default one
+        fs::write(cache_dir.join("pk.json"), "pk").map_err(HarpocratesError::cache(&cache_dir))?;
+        Sealed::seal(b"sk", "passphrase")?.write(&cache_dir.join("sk.json"))?;
+        assert!(profiles.adopt_legacy(&cache_dir)?);
+        assert!(!cache_dir.join("sk.json").exists());
+        assert!(profiles.exists(DEFAULT_PROFILE)?);
+
+        let keys = profiles.export(DEFAULT_PROFILE)?;
+        profiles.import("voter", &keys)?;
+        assert!(profiles.import("voter", &keys).is_err());
+        assert!(profiles.file("../voter", "sk.json").is_err());
+
+        profiles.set_admin("contract", DEFAULT_PROFILE)?;
+        assert!(profiles.set_admin("contract", "nobody").is_err());
+        assert_eq!(
+            profiles.admin_of("contract")?,
+            Some(DEFAULT_PROFILE.to_string())
+        );
+        assert_eq!(profiles.list()?[0].administers, vec!["contract"]);
+        assert_eq!(profiles.list()?[1].name, "voter");
+
+        // the admin of a poll is only deleted on purpose
+        assert!(profiles.delete(DEFAULT_PROFILE, false).is_err());
+        assert_eq!(profiles.delete(DEFAULT_PROFILE, true)?, vec!["contract"]);
+        assert_eq!(profiles.admin_of("contract")?, None);
+        assert_eq!(profiles.list()?.len(), 1);
+        Ok(())
+    }
+}
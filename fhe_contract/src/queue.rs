@@ -0,0 +1,168 @@
+use crate::arweave::{encode_hex, sha_256};
+use crate::error::HarpocratesError;
+use crate::transaction::VoteData;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A ballot waiting to be summed with others before it is published
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StagedBallot {
+    pub eth_address: String,
+    /// The encrypted ballot and its proof, as `vote` would publish them
+    pub vote: VoteData,
+}
+
+/// Directory where the voters of a contract stage their ballots and sign their sum.
+///
+/// It can be local, or shared by the voters to relay the ballots (e.g. a synced folder).
+/// Ballots are saved as `<root>/<contract_id>/ballots/<hash>.json`, signatures of an
+/// aggregate as `<root>/<contract_id>/signatures/<hash of its message>.json`.
+pub struct BallotQueue {
+    root: PathBuf,
+}
+
+impl BallotQueue {
+    pub fn new(root: PathBuf) -> Self {
+        BallotQueue { root }
+    }
+
+    fn dir(&self, contract_id: &str, kind: &str) -> Result<PathBuf, HarpocratesError> {
+        let dir = self.root.join(contract_id).join(kind);
+        fs::create_dir_all(&dir).map_err(HarpocratesError::cache(&dir))?;
+        Ok(dir)
+    }
+
+    pub fn stage(&self, contract_id: &str, ballot: &StagedBallot) -> Result<(), HarpocratesError> {
+        let name = encode_hex(&sha_256(ballot.vote.data.as_bytes()));
+        let path = self
+            .dir(contract_id, "ballots")?
+            .join(format!("{}.json", name));
+        fs::write(&path, serde_json::to_string(ballot)?).map_err(HarpocratesError::cache(&path))
+    }
+
+    /// The ballots staged for the contract, always in the same order
+    pub fn staged(&self, contract_id: &str) -> Result<Vec<StagedBallot>, HarpocratesError> {
+        let dir = self.dir(contract_id, "ballots")?;
+        let mut paths = fs::read_dir(&dir)
+            .map_err(HarpocratesError::cache(&dir))?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(HarpocratesError::cache(&dir))?;
+        paths.sort();
+
+        paths
+            .iter()
+            .map(|path| {
+                let content = fs::read_to_string(path).map_err(HarpocratesError::cache(path))?;
+                Ok(serde_json::from_str(&content)?)
+            })
+            .collect()
+    }
+
+    /// Adds the signature of `eth_address` over `message`, returns every signature so far
+    pub fn sign(
+        &self,
+        contract_id: &str,
+        message: &[u8],
+        eth_address: &str,
+        signature: &str,
+    ) -> Result<BTreeMap<String, String>, HarpocratesError> {
+        let path = self.signatures_path(contract_id, message)?;
+        let mut signatures = read_signatures(&path)?;
+        signatures.insert(eth_address.to_lowercase(), signature.to_string());
+        fs::write(&path, serde_json::to_string(&signatures)?)
+            .map_err(HarpocratesError::cache(&path))?;
+        Ok(signatures)
+    }
+
+    /// The signatures over `message` so far, keyed by lowercased address
+    pub fn signatures(
+        &self,
+        contract_id: &str,
+        message: &[u8],
+    ) -> Result<BTreeMap<String, String>, HarpocratesError> {
+        read_signatures(&self.signatures_path(contract_id, message)?)
+    }
+
+    fn signatures_path(
+        &self,
+        contract_id: &str,
+        message: &[u8],
+    ) -> Result<PathBuf, HarpocratesError> {
+        let name = encode_hex(&sha_256(message));
+        Ok(self
+            .dir(contract_id, "signatures")?
+            .join(format!("{}.json", name)))
+    }
+
+    /// Empties the queue of the contract once its aggregate is published
+    pub fn clear(&self, contract_id: &str) -> Result<(), HarpocratesError> {
+        let dir = self.root.join(contract_id);
+        fs::remove_dir_all(&dir).map_err(HarpocratesError::cache(&dir))
+    }
+}
+
+fn read_signatures(path: &Path) -> Result<BTreeMap<String, String>, HarpocratesError> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let content = fs::read_to_string(path).map_err(HarpocratesError::cache(path))?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+    use crate::transaction::ZKProof;
+
+    fn ballot(eth_address: &str, data: &str) -> StagedBallot {
+        StagedBallot {
+            eth_address: eth_address.to_string(),
+            vote: VoteData {
+                data: data.to_string(),
+                zkp: ZKProof {
+                    proof: String::new(),
+                    public: String::new(),
+                },
+                membership: None,
+            },
+        }
+    }
+
+    #[test]
+    fn it_stages_ballots_and_signatures() -> Result<(), HarpocratesError> {
+        let root = temp_dir("queue");
+        let queue = BallotQueue::new(root.clone());
+
+        queue.stage("contract", &ballot("0xbob", "[2]"))?;
+        queue.stage("contract", &ballot("0xalice", "[1]"))?;
+        queue.stage("another", &ballot("0xcarol", "[3]"))?;
+
+        // the same order for everyone, whatever the order they were staged in
+        let staged = queue.staged("contract")?;
+        assert_eq!(staged.len(), 2);
+        let mut names: Vec<String> = staged
+            .iter()
+            .map(|b| encode_hex(&sha_256(b.vote.data.as_bytes())))
+            .collect();
+        let order = names.clone();
+        names.sort();
+        assert_eq!(names, order);
+
+        queue.sign("contract", b"sum", "0xAlice", "sig a")?;
+        let signatures = queue.sign("contract", b"sum", "0xbob", "sig b")?;
+        assert_eq!(signatures.len(), 2);
+        assert_eq!(signatures["0xalice"], "sig a");
+        assert!(queue.signatures("contract", b"other sum")?.is_empty());
+
+        queue.clear("contract")?;
+        assert!(queue.staged("contract")?.is_empty());
+        assert_eq!(queue.staged("another")?.len(), 1);
+
+        fs::remove_dir_all(root).ok();
+        Ok(())
+    }
+}
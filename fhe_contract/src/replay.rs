@@ -0,0 +1,394 @@
+use crate::transaction::TxMeta;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::mem;
+
+/// Which ballot counts when the same identity votes more than once
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicatePolicy {
+    /// The first vote counts, the next ones are ignored
+    First,
+    /// The last vote counts, it replaces the previous one
+    Last,
+    /// None of the votes count
+    RejectAll,
+}
+
+/// What identifies a voter
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Identity {
+    /// The `Eth-Address` tag
+    EthAddress,
+    /// The Arweave wallet paying for the transaction
+    ArweaveOwner,
+}
+
+impl Identity {
+    fn of(&self, meta: &TxMeta) -> String {
+        match self {
+            Identity::EthAddress => Identity::address(&meta.eth_address),
+            Identity::ArweaveOwner => meta.owner.clone(),
+        }
+    }
+
+    /// Key of the voter behind an Eth address, aggregates only know their voters by address
+    pub fn address(eth_address: &str) -> String {
+        // addresses may or may not be checksummed
+        eth_address.to_lowercase()
+    }
+}
+
+/// What to do with the tally when a ballot comes in
+#[derive(Debug, PartialEq)]
+pub enum Decision<B> {
+    /// Add the ballot
+    Add,
+    /// Take the given ballot out, then add the new one
+    Replace(B),
+    /// Take the given ballot out and don't add the new one
+    Remove(B),
+    /// Leave the tally as is
+    Skip,
+}
+
+/// A vote from an identity that already voted
+#[derive(Debug, PartialEq)]
+pub struct Duplicate {
+    pub identity: String,
+    pub tx_id: String,
+    pub outcome: String,
+}
+
+// transaction and ballot counted for an identity, `None` once they are all rejected
+type Counted<B> = Option<(String, B)>;
+
+/// Keeps track of who voted while replaying, so only one ballot per identity is counted
+pub struct Ballots<B> {
+    policy: DuplicatePolicy,
+    identity: Identity,
+    counted: HashMap<String, Counted<B>>,
+    // aggregate counted for every address that is part of one, their ballot can't be taken out of it
+    aggregated: HashMap<String, String>,
+    // addresses that voted on their own, whatever identifies them
+    voted: HashSet<String>,
+    pub duplicates: Vec<Duplicate>,
+    // what the last ballot recorded changed, see `revoke`
+    undo: Undo<B>,
+}
+
+struct Undo<B> {
+    // the identity and what was counted for it before, if anything
+    counted: Option<(String, Option<Counted<B>>)>,
+    aggregated: Vec<String>,
+    voted: Option<String>,
+    duplicates: usize,
+}
+
+impl<B> Undo<B> {
+    fn new(duplicates: usize) -> Self {
+        Undo {
+            counted: None,
+            aggregated: vec![],
+            voted: None,
+            duplicates,
+        }
+    }
+}
+
+impl<B: Clone> Ballots<B> {
+    pub fn new(policy: DuplicatePolicy, identity: Identity) -> Self {
+        Ballots {
+            policy,
+            identity,
+            counted: HashMap::new(),
+            aggregated: HashMap::new(),
+            voted: HashSet::new(),
+            duplicates: vec![],
+            undo: Undo::new(0),
+        }
+    }
+
+    /// Takes the last ballot recorded back, when it could not be counted after all
+    pub fn revoke(&mut self) {
+        let undo = mem::replace(&mut self.undo, Undo::new(self.duplicates.len()));
+        match undo.counted {
+            Some((identity, Some(previous))) => {
+                self.counted.insert(identity, previous);
+            }
+            Some((identity, None)) => {
+                self.counted.remove(&identity);
+            }
+            None => {}
+        }
+        for address in undo.aggregated {
+            self.aggregated.remove(&address);
+        }
+        if let Some(address) = undo.voted {
+            self.voted.remove(&address);
+        }
+        self.duplicates.truncate(undo.duplicates);
+        self.undo.duplicates = self.duplicates.len();
+    }
+
+    /// Records a valid ballot, in the order the votes were published
+    pub fn record(&mut self, meta: &TxMeta, ballot: &B) -> Decision<B> {
+        self.undo = Undo::new(self.duplicates.len());
+        let identity = self.identity.of(meta);
+        let address = Identity::address(&meta.eth_address);
+        if let Some(aggregate) = self.aggregated.get(&address) {
+            let outcome = format!("ignored, counted in aggregate {}", aggregate);
+            self.duplicates.push(Duplicate {
+                identity,
+                tx_id: meta.id.clone(),
+                outcome,
+            });
+            return Decision::Skip;
+        }
+
+        if self.voted.insert(address.clone()) {
+            self.undo.voted = Some(address);
+        }
+        self.record_as(identity, &meta.id, ballot)
+    }
+
+    /// Same, for a voter identified by something else than its transaction, e.g. the
+    /// nullifier of an anonymous vote
+    pub fn record_for(&mut self, identity: String, tx_id: &str, ballot: &B) -> Decision<B> {
+        self.undo = Undo::new(self.duplicates.len());
+        self.record_as(identity, tx_id, ballot)
+    }
+
+    fn record_as(&mut self, identity: String, tx_id: &str, ballot: &B) -> Decision<B> {
+        let before = self.counted.get(&identity).cloned();
+        self.undo.counted = Some((identity.clone(), before.clone()));
+
+        let previous = match before {
+            None => {
+                self.counted
+                    .insert(identity, Some((tx_id.to_string(), ballot.clone())));
+                return Decision::Add;
+            }
+            Some(previous) => previous,
+        };
+
+        let (outcome, decision) = match (self.policy, previous) {
+            (_, None) => ("rejected".to_string(), Decision::Skip),
+            (DuplicatePolicy::First, Some((previous_tx, _))) => {
+                (format!("ignored, {} counts", previous_tx), Decision::Skip)
+            }
+            (DuplicatePolicy::Last, Some((previous_tx, previous))) => {
+                self.counted
+                    .insert(identity.clone(), Some((tx_id.to_string(), ballot.clone())));
+                (
+                    format!("replaces {}", previous_tx),
+                    Decision::Replace(previous),
+                )
+            }
+            (DuplicatePolicy::RejectAll, Some((previous_tx, previous))) => {
+                self.counted.insert(identity.clone(), None);
+                (
+                    format!("rejected along with {}", previous_tx),
+                    Decision::Remove(previous),
+                )
+            }
+        };
+
+        self.duplicates.push(Duplicate {
+            identity,
+            tx_id: tx_id.to_string(),
+            outcome,
+        });
+        decision
+    }
+
+    /// Records an aggregate of the ballots of the voters with these Eth addresses. It is only
+    /// added if none of them voted before, and their next votes are ignored whatever the policy
+    pub fn record_aggregate(&mut self, eth_addresses: &[String], tx_id: &str) -> Decision<B> {
+        self.undo = Undo::new(self.duplicates.len());
+        let addresses: Vec<String> = eth_addresses
+            .iter()
+            .map(|address| Identity::address(address))
+            .collect();
+
+        let mut seen = HashSet::new();
+        let voted = addresses.iter().find(|address| {
+            self.voted.contains(*address)
+                || self.aggregated.contains_key(*address)
+                || !seen.insert(*address)
+        });
+
+        if let Some(address) = voted {
+            self.duplicates.push(Duplicate {
+                identity: address.clone(),
+                tx_id: tx_id.to_string(),
+                outcome: "aggregate ignored, already voted".to_string(),
+            });
+            return Decision::Skip;
+        }
+
+        for address in &addresses {
+            self.aggregated.insert(address.clone(), tx_id.to_string());
+        }
+        self.undo.aggregated = addresses;
+        Decision::Add
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::{Action, Input};
+
+    fn vote(id: &str, eth_address: &str) -> TxMeta {
+        TxMeta {
+            id: id.to_string(),
+            cursor: "0".to_string(),
+            owner: "local".to_string(),
+            block: None,
+            contract: "contract".to_string(),
+            input: Input::new(Action::Vote),
+            unix_time: 0,
+            eth_address: eth_address.to_string(),
+            eth_signature: String::new(),
+            data_sha256: String::new(),
+        }
+    }
+
+    // the decisions for alice voting 1, bob 2, then alice 3 and 4
+    fn replay(policy: DuplicatePolicy) -> (Vec<Decision<u32>>, Vec<Duplicate>) {
+        let mut ballots = Ballots::new(policy, Identity::EthAddress);
+        let decisions = vec![
+            ballots.record(&vote("a1", "0xAlice"), &1),
+            ballots.record(&vote("b1", "0xbob"), &2),
+            ballots.record(&vote("a2", "0xalice"), &3),
+            ballots.record(&vote("a3", "0xalice"), &4),
+        ];
+        (decisions, ballots.duplicates)
+    }
+
+    #[test]
+    fn it_applies_the_policy() {
+        use Decision::*;
+
+        let (decisions, duplicates) = replay(DuplicatePolicy::First);
+        assert_eq!(decisions, vec![Add, Add, Skip, Skip]);
+        assert_eq!(duplicates.len(), 2);
+        assert_eq!(duplicates[0].identity, "0xalice");
+        assert_eq!(duplicates[0].outcome, "ignored, a1 counts");
+
+        let (decisions, duplicates) = replay(DuplicatePolicy::Last);
+        assert_eq!(decisions, vec![Add, Add, Replace(1), Replace(3)]);
+        assert_eq!(
+            duplicates
+                .iter()
+                .map(|d| d.outcome.as_str())
+                .collect::<Vec<_>>(),
+            vec!["replaces a1", "replaces a2"]
+        );
+
+        let (decisions, duplicates) = replay(DuplicatePolicy::RejectAll);
+        assert_eq!(decisions, vec![Add, Add, Remove(1), Skip]);
+        assert_eq!(
+            duplicates
+                .iter()
+                .map(|d| d.tx_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a2", "a3"]
+        );
+    }
+
+    #[test]
+    fn it_revokes_a_ballot_that_could_not_be_counted() {
+        use Decision::*;
+
+        let mut ballots = Ballots::new(DuplicatePolicy::Last, Identity::EthAddress);
+        assert_eq!(ballots.record(&vote("a1", "0xalice"), &1), Add);
+        assert_eq!(ballots.record(&vote("a2", "0xalice"), &2), Replace(1));
+        ballots.revoke();
+        assert!(ballots.duplicates.is_empty());
+
+        // a1 still counts, and bob never voted
+        assert_eq!(ballots.record(&vote("a3", "0xalice"), &3), Replace(1));
+        assert_eq!(ballots.record(&vote("b1", "0xbob"), &4), Add);
+        ballots.revoke();
+        assert_eq!(
+            ballots.record_aggregate(&["0xbob".to_string(), "0xcarol".to_string()], "bc"),
+            Add
+        );
+        ballots.revoke();
+        assert_eq!(ballots.record(&vote("c1", "0xcarol"), &5), Add);
+    }
+
+    #[test]
+    fn it_can_identify_voters_by_owner() {
+        let mut ballots = Ballots::new(DuplicatePolicy::First, Identity::ArweaveOwner);
+
+        // different addresses, paid by the same wallet
+        assert_eq!(ballots.record(&vote("a", "0xalice"), &1), Decision::Add);
+        assert_eq!(ballots.record(&vote("b", "0xbob"), &2), Decision::Skip);
+    }
+
+    #[test]
+    fn it_counts_aggregates_once() {
+        use Decision::*;
+
+        let mut ballots = Ballots::new(DuplicatePolicy::Last, Identity::EthAddress);
+        let voters = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(ballots.record(&vote("a1", "0xalice"), &1), Add);
+        // alice already voted, and carol can't be in an aggregate twice
+        assert_eq!(
+            ballots.record_aggregate(&voters(&["0xbob", "0xalice"]), "ab"),
+            Skip
+        );
+        assert_eq!(
+            ballots.record_aggregate(&voters(&["0xcarol", "0xcarol"]), "cc"),
+            Skip
+        );
+        assert_eq!(
+            ballots.record_aggregate(&voters(&["0xbob", "0xcarol"]), "bc"),
+            Add
+        );
+
+        // bob's ballot can't be replaced, it is part of a sum
+        assert_eq!(ballots.record(&vote("b1", "0xbob"), &2), Skip);
+        assert_eq!(
+            ballots.record_aggregate(&voters(&["0xcarol", "0xdave"]), "cd"),
+            Skip
+        );
+        assert_eq!(
+            ballots.duplicates[2].outcome,
+            "ignored, counted in aggregate bc"
+        );
+    }
+
+    #[test]
+    fn it_counts_aggregates_once_by_owner() {
+        use Decision::*;
+
+        let mut ballots = Ballots::new(DuplicatePolicy::Last, Identity::ArweaveOwner);
+        let voters = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(ballots.record(&vote("a1", "0xAlice"), &1), Add);
+        assert_eq!(
+            ballots.record_aggregate(&voters(&["0xbob", "0xalice"]), "ab"),
+            Skip
+        );
+        assert_eq!(
+            ballots.record_aggregate(&voters(&["0xBob", "0xcarol"]), "bc"),
+            Add
+        );
+
+        // bob's vote is known by its owner, it is still counted in the aggregate
+        let mut bob = vote("b1", "0xbob");
+        bob.owner = "bob's wallet".to_string();
+        assert_eq!(ballots.record(&bob, &2), Skip);
+        assert_eq!(
+            ballots.duplicates[1].outcome,
+            "ignored, counted in aggregate bc"
+        );
+    }
+}
@@ -0,0 +1,623 @@
+use crate::action::{Action, Input};
+use crate::arweave::{
+    get_app_name, get_eth_metadata, get_unix_timestamp, sha_256, tag_pairs, ContractType,
+};
+use crate::error::HarpocratesError;
+use crate::ethereum::EthSigner;
+use crate::transaction::{contract_id, InteractionTx, PublicKeyTx, RawTx, SourceTx, ZkParamsTx};
+use arloader::transaction::Base64;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::PathBuf;
+
+/// Where contracts and their interactions are published to and read from.
+///
+/// Writes return the id of the transaction and the id of the contract it belongs to.
+/// Reads return the transactions in the order they were published.
+#[async_trait(?Send)]
+pub trait ContractStore {
+    /// Publishes `data` for the contract with the given `Input`, signed by our Eth address.
+    ///
+    /// Anonymous votes are the exception, they have neither Eth-Address nor Eth-Signature:
+    /// the proof in their data says they may vote.
+    async fn publish(
+        &self,
+        contract_id: &str,
+        input: Input,
+        contract_type: ContractType,
+        data: Vec<u8>,
+    ) -> Result<(String, String), HarpocratesError> {
+        let (eth_address, eth_signature) = match input.action {
+            Action::AnonymousVote => (String::new(), String::new()),
+            _ => self.eth_sign(&data).await?,
+        };
+        let tx_id = self
+            .publish_tagged(
+                contract_id,
+                &input,
+                contract_type,
+                data,
+                &eth_address,
+                &eth_signature,
+            )
+            .await?;
+        Ok((tx_id, contract_id.to_string()))
+    }
+
+    /// Same, with the given identity tags, which are left out when empty. Returns the id of
+    /// the transaction
+    async fn publish_tagged(
+        &self,
+        contract_id: &str,
+        input: &Input,
+        contract_type: ContractType,
+        data: Vec<u8>,
+        eth_address: &str,
+        eth_signature: &str,
+    ) -> Result<String, HarpocratesError>;
+
+    async fn deploy_contract(
+        &self,
+        contract_data: &str,
+    ) -> Result<(String, String), HarpocratesError> {
+        let (eth_address, eth_signature) = self.eth_sign(contract_data.as_bytes()).await?;
+
+        // the id can only be reproduced by the signed source, see SourceTx::verify
+        let contract_id = contract_id(&eth_address, &eth_signature, contract_data.as_bytes());
+
+        let tx_id = self
+            .publish_tagged(
+                &contract_id,
+                &Input::new(Action::Deploy),
+                ContractType::Source,
+                contract_data.as_bytes().to_vec(),
+                &eth_address,
+                &eth_signature,
+            )
+            .await?;
+        Ok((tx_id, contract_id))
+    }
+
+    async fn deploy_zksnark(
+        &self,
+        contract_id: &str,
+        contract_data: Vec<u8>,
+    ) -> Result<(String, String), HarpocratesError> {
+        self.publish(
+            contract_id,
+            Input::new(Action::ZkSnark),
+            ContractType::ZkSnark,
+            contract_data,
+        )
+        .await
+    }
+
+    async fn initialize_state(
+        &self,
+        contract_id: &str,
+        initial_state: String,
+    ) -> Result<(String, String), HarpocratesError> {
+        self.publish(
+            contract_id,
+            Input::new(Action::InitState),
+            ContractType::Interaction,
+            initial_state.into_bytes(),
+        )
+        .await
+    }
+
+    async fn vote(
+        &self,
+        contract_id: &str,
+        vote_data: String,
+    ) -> Result<(String, String), HarpocratesError> {
+        let input = Input::new(Action::Vote).proven_by(self.validity_proof(contract_id).await);
+        self.publish(
+            contract_id,
+            input,
+            ContractType::Interaction,
+            vote_data.into_bytes(),
+        )
+        .await
+    }
+
+    /// Publishes a vote without the tags saying who sent it
+    async fn vote_anonymously(
+        &self,
+        contract_id: &str,
+        vote_data: String,
+    ) -> Result<(String, String), HarpocratesError> {
+        let input =
+            Input::new(Action::AnonymousVote).proven_by(self.validity_proof(contract_id).await);
+        self.publish(
+            contract_id,
+            input,
+            ContractType::Interaction,
+            vote_data.into_bytes(),
+        )
+        .await
+    }
+
+    /// Publishes the sum of several votes, as JSON `AggregateVote`
+    async fn vote_aggregate(
+        &self,
+        contract_id: &str,
+        aggregate: String,
+    ) -> Result<(String, String), HarpocratesError> {
+        let input =
+            Input::new(Action::AggregateVote).proven_by(self.validity_proof(contract_id).await);
+        self.publish(
+            contract_id,
+            input,
+            ContractType::Interaction,
+            aggregate.into_bytes(),
+        )
+        .await
+    }
+
+    /// Publishes the FHE public key voters encrypt their ballots to
+    async fn publish_pk(
+        &self,
+        contract_id: &str,
+        public_key: String,
+    ) -> Result<(String, String), HarpocratesError> {
+        self.publish(
+            contract_id,
+            Input::new(Action::PublicKey),
+            ContractType::PublicKey,
+            public_key.into_bytes(),
+        )
+        .await
+    }
+
+    /// Publishes members added to or removed from the allowlist, as JSON `AllowlistUpdate`
+    async fn update_allowlist(
+        &self,
+        contract_id: &str,
+        update: String,
+    ) -> Result<(String, String), HarpocratesError> {
+        self.publish(
+            contract_id,
+            Input::new(Action::Allowlist),
+            ContractType::Interaction,
+            update.into_bytes(),
+        )
+        .await
+    }
+
+    /// Publishes the end of the poll, no vote counts after it
+    async fn close(&self, contract_id: &str) -> Result<(String, String), HarpocratesError> {
+        self.publish(
+            contract_id,
+            Input::new(Action::Close),
+            ContractType::Interaction,
+            b"{}".to_vec(),
+        )
+        .await
+    }
+
+    /// Publishes the decrypted tally, as JSON `PublishedResult`
+    async fn publish_result(
+        &self,
+        contract_id: &str,
+        result: String,
+    ) -> Result<(String, String), HarpocratesError> {
+        self.publish(
+            contract_id,
+            Input::new(Action::Decrypt),
+            ContractType::Interaction,
+            result.into_bytes(),
+        )
+        .await
+    }
+
+    /// Returns the source and the interactions of the contract
+    async fn fetch_latest_state(
+        &self,
+        contract_id: String,
+    ) -> Result<(Vec<SourceTx>, Vec<InteractionTx>), HarpocratesError>;
+
+    async fn fetch_source(&self, contract_id: String) -> Result<Vec<SourceTx>, HarpocratesError>;
+
+    /// Returns every zk params published for the contract, by anyone
+    async fn fetch_zk_params(
+        &self,
+        contract_id: String,
+    ) -> Result<Vec<ZkParamsTx>, HarpocratesError>;
+
+    /// Returns the first zk params the deployer of `source` signed, anyone can tag params
+    /// with the ID of a contract
+    async fn fetch_zk(&self, source: &SourceTx) -> Result<ZkParamsTx, HarpocratesError> {
+        let deployer = &source.meta.eth_address;
+        self.fetch_zk_params(source.meta.contract.clone())
+            .await?
+            .into_iter()
+            .find(|zk| {
+                zk.meta.eth_address.eq_ignore_ascii_case(deployer)
+                    && zk.meta.verify_eth_signature().is_ok()
+            })
+            .ok_or_else(|| {
+                HarpocratesError::Contract(format!(
+                    "the deployer {} published no zk params for contract {}",
+                    deployer, source.meta.contract
+                ))
+            })
+    }
+
+    /// The transaction of the zk params votes are proven with, referenced by their `Input` tag
+    async fn validity_proof(&self, contract_id: &str) -> Option<String> {
+        let sources = self.fetch_source(contract_id.to_string()).await.ok()?;
+        let source = sources.iter().find(|source| source.verify().is_ok())?;
+        self.fetch_zk(source).await.ok().map(|zk| zk.meta.id)
+    }
+
+    /// Returns every public key published for the contract, by anyone
+    async fn fetch_public_keys(
+        &self,
+        contract_id: String,
+    ) -> Result<Vec<PublicKeyTx>, HarpocratesError>;
+
+    /// Returns the first public key the deployer of `source` signed, anyone can tag a key
+    /// with the ID of a contract
+    async fn fetch_pk(&self, source: &SourceTx) -> Result<PublicKeyTx, HarpocratesError> {
+        let deployer = &source.meta.eth_address;
+        self.fetch_public_keys(source.meta.contract.clone())
+            .await?
+            .into_iter()
+            .find(|pk| {
+                pk.meta.eth_address.eq_ignore_ascii_case(deployer)
+                    && pk.meta.verify_eth_signature().is_ok()
+            })
+            .ok_or_else(|| {
+                HarpocratesError::Key(format!(
+                    "the deployer {} published no public key for contract {}",
+                    deployer, source.meta.contract
+                ))
+            })
+    }
+
+    async fn wait_till_mined(&self, tx_id: &str) -> Result<(), HarpocratesError>;
+
+    /// Ethereum address the transactions are signed with
+    async fn eth_address(&self) -> Result<String, HarpocratesError>;
+
+    /// Signs `data` the way transactions are, returns the address and the signature
+    async fn eth_sign(&self, data: &[u8]) -> Result<(String, String), HarpocratesError>;
+}
+
+// the keys that can be read, a transaction that can't doesn't hide the others
+pub(crate) fn public_keys(txs: Vec<RawTx>) -> Vec<PublicKeyTx> {
+    txs.into_iter()
+        .filter_map(|tx| PublicKeyTx::try_from(tx).ok())
+        .collect()
+}
+
+/// Offline store keeping every transaction in a local directory.
+///
+/// Each transaction is saved as `<root>/<contract_id>/<app_name>/<seq>-<tx_id>.json`,
+/// transactions are final as soon as they are written. They are signed by `signer`
+/// just like on Arweave.
+pub struct LocalStore {
+    root: PathBuf,
+    signer: EthSigner,
+}
+
+impl LocalStore {
+    pub fn new(root: PathBuf, signer: EthSigner) -> Result<Self, HarpocratesError> {
+        fs::create_dir_all(&root).map_err(HarpocratesError::cache(&root))?;
+        Ok(LocalStore { root, signer })
+    }
+
+    fn dir(&self, contract_id: &str, contract_type: ContractType) -> PathBuf {
+        self.root
+            .join(contract_id)
+            .join(get_app_name(contract_type))
+    }
+
+    // every transaction is mined in its own block, so the height is the number of
+    // transactions of the contract
+    fn height(&self, contract_id: &str) -> Result<usize, HarpocratesError> {
+        let mut height = 0;
+        for contract_type in [
+            ContractType::Source,
+            ContractType::Interaction,
+            ContractType::ZkSnark,
+            ContractType::PublicKey,
+        ] {
+            let dir = self.dir(contract_id, contract_type);
+            if dir.exists() {
+                height += fs::read_dir(&dir)
+                    .map_err(HarpocratesError::cache(&dir))?
+                    .count();
+            }
+        }
+        Ok(height)
+    }
+
+    // returns the stored transactions, in the order they were written
+    fn read(
+        &self,
+        contract_id: &str,
+        contract_type: ContractType,
+    ) -> Result<Vec<RawTx>, HarpocratesError> {
+        let dir = self.dir(contract_id, contract_type);
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut paths: Vec<PathBuf> = fs::read_dir(&dir)
+            .and_then(|entries| entries.map(|entry| entry.map(|e| e.path())).collect())
+            .map_err(HarpocratesError::cache(&dir))?;
+        paths.sort();
+
+        let mut records = vec![];
+        for (cursor, path) in paths.iter().enumerate() {
+            let raw = fs::read(path).map_err(HarpocratesError::cache(path))?;
+            let mut record: Value = serde_json::from_slice(&raw)?;
+            let data: Base64 = record["data"]
+                .as_str()
+                .and_then(|data| data.parse().ok())
+                .ok_or_else(|| {
+                    HarpocratesError::Serialization(format!("invalid data in {}", path.display()))
+                })?;
+            record["cursor"] = json!(cursor.to_string());
+            records.push(RawTx::from_record(&record, data.0)?);
+        }
+        Ok(records)
+    }
+}
+
+#[async_trait(?Send)]
+impl ContractStore for LocalStore {
+    async fn publish_tagged(
+        &self,
+        contract_id: &str,
+        input: &Input,
+        contract_type: ContractType,
+        data: Vec<u8>,
+        eth_address: &str,
+        eth_signature: &str,
+    ) -> Result<String, HarpocratesError> {
+        let height = self.height(contract_id)? + 1;
+        let dir = self.dir(contract_id, contract_type);
+        fs::create_dir_all(&dir).map_err(HarpocratesError::cache(&dir))?;
+
+        let unix_timestamp = get_unix_timestamp();
+        let seq = fs::read_dir(&dir)
+            .map_err(HarpocratesError::cache(&dir))?
+            .count();
+
+        let mut id_data = data.clone();
+        id_data.extend_from_slice(format!("{}{}", unix_timestamp, seq).as_bytes());
+        let tx_id = Base64(sha_256(&id_data).to_vec()).to_string();
+
+        let tags: Vec<Value> = tag_pairs(
+            contract_id,
+            &unix_timestamp,
+            input,
+            contract_type,
+            eth_address,
+            eth_signature,
+        )
+        .into_iter()
+        .map(|(name, value)| json!({"name": name, "value": value}))
+        .collect();
+
+        let record = json!({
+            "id": tx_id,
+            "owner": "local",
+            "block": {"height": height, "timestamp": unix_timestamp.parse::<u64>().unwrap_or_default()},
+            "tags": tags,
+            "data": Base64(data).to_string(),
+        });
+
+        let path = dir.join(format!("{:08}-{}.json", seq, tx_id));
+        fs::write(&path, record.to_string()).map_err(HarpocratesError::cache(&path))?;
+
+        Ok(tx_id)
+    }
+
+    async fn fetch_latest_state(
+        &self,
+        contract_id: String,
+    ) -> Result<(Vec<SourceTx>, Vec<InteractionTx>), HarpocratesError> {
+        let source = self.fetch_source(contract_id.clone()).await?;
+        let interactions = self
+            .read(&contract_id, ContractType::Interaction)?
+            .into_iter()
+            .map(InteractionTx::from)
+            .collect();
+        Ok((source, interactions))
+    }
+
+    async fn fetch_source(&self, contract_id: String) -> Result<Vec<SourceTx>, HarpocratesError> {
+        self.read(&contract_id, ContractType::Source)?
+            .into_iter()
+            .map(SourceTx::try_from)
+            .collect()
+    }
+
+    async fn fetch_zk_params(
+        &self,
+        contract_id: String,
+    ) -> Result<Vec<ZkParamsTx>, HarpocratesError> {
+        Ok(self
+            .read(&contract_id, ContractType::ZkSnark)?
+            .into_iter()
+            .map(ZkParamsTx::from)
+            .collect())
+    }
+
+    async fn fetch_public_keys(
+        &self,
+        contract_id: String,
+    ) -> Result<Vec<PublicKeyTx>, HarpocratesError> {
+        Ok(public_keys(
+            self.read(&contract_id, ContractType::PublicKey)?,
+        ))
+    }
+
+    async fn wait_till_mined(&self, _tx_id: &str) -> Result<(), HarpocratesError> {
+        // nothing to wait for, transactions are final once written
+        Ok(())
+    }
+
+    async fn eth_address(&self) -> Result<String, HarpocratesError> {
+        self.signer.eth_address().await
+    }
+
+    async fn eth_sign(&self, data: &[u8]) -> Result<(String, String), HarpocratesError> {
+        get_eth_metadata(&self.signer, &data.to_vec()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calculator::{calculate, decrypt, get_initial_state};
+    use crate::compiler::compile;
+    use crate::contract::{source_nonce, ContractSource, HarpocratesContract, VoteContract};
+    use crate::poll::PollWindow;
+    use crate::testing::{local_store, temp_dir, OTHER_ETH_KEY, TEST_ETH_KEY};
+    use crate::transaction::{Interaction, VoteData, ZKProof};
+    use sunscreen::{Application, Ciphertext, Runtime};
+
+    fn test_store(name: &str) -> LocalStore {
+        local_store(&temp_dir(name), TEST_ETH_KEY)
+    }
+
+    #[tokio::test]
+    async fn it_runs_a_poll_offline() -> Result<(), Box<dyn std::error::Error>> {
+        let store = test_store("poll");
+        let contract = VoteContract::new(3);
+
+        let contract_json = compile(&contract)?;
+        let app: Application = serde_json::from_str(&contract_json)?;
+        let runtime = Runtime::new(app.params())?;
+        let (pk, sk) = runtime.generate_keys()?;
+
+        // deploy -> init state -> 2 votes
+        let source = serde_json::to_string(&ContractSource {
+            app: serde_json::from_str(&contract_json)?,
+            contract,
+            allowlist: None,
+            window: PollWindow::default(),
+            nonce: source_nonce(),
+        })?;
+        let (_, contract_id) = store.deploy_contract(&source).await?;
+
+        let init = get_initial_state(&contract, &app, &pk)?;
+        store.initialize_state(&contract_id, init).await?;
+
+        // someone else tags their key with the contract first
+        let squatter = local_store(&store.root, OTHER_ETH_KEY);
+        let (squatter_pk, _) = runtime.generate_keys()?;
+        squatter
+            .publish_pk(&contract_id, serde_json::to_string(&squatter_pk)?)
+            .await?;
+        store
+            .publish_pk(&contract_id, serde_json::to_string(&pk)?)
+            .await?;
+
+        // voters only know the published key, the one of the deployer
+        let source_tx = store.fetch_source(contract_id.clone()).await?.remove(0);
+        let admin_pk = store.fetch_pk(&source_tx).await?;
+        assert_eq!(admin_pk.public_key, serde_json::to_string(&pk)?);
+        let admin_pk = admin_pk.key()?;
+
+        for index in [2, 2] {
+            let vote = VoteData {
+                data: serde_json::to_string(&contract.encode_input(&runtime, &admin_pk, index)?)?,
+                // replayed below without checking proofs
+                zkp: ZKProof {
+                    proof: String::new(),
+                    public: String::new(),
+                },
+                membership: None,
+            };
+            let (tx_id, _) = store
+                .vote(&contract_id, serde_json::to_string(&vote)?)
+                .await?;
+            store.wait_till_mined(&tx_id).await?;
+        }
+
+        // tally
+        let (source, interactions) = store.fetch_latest_state(contract_id).await?;
+        // the id can't be reused for another source
+        source[0].verify()?;
+        let mut squatted = source[0].clone();
+        squatted.source.push(' ');
+        assert!(squatted.verify().is_err());
+        let source: ContractSource<VoteContract> = source[0].contract()?;
+        assert_eq!(source.contract.options, 3);
+        assert_eq!(interactions.len(), 3);
+        for intx in &interactions {
+            intx.meta.verify_eth_signature()?;
+        }
+
+        let mut state = match &interactions[0].interaction {
+            Interaction::InitState(state) => state.clone(),
+            _ => panic!("expected the init state first"),
+        };
+        for intx in &interactions[1..] {
+            let input: Vec<Ciphertext> = match &intx.interaction {
+                Interaction::Vote(vote) => serde_json::from_str(&vote.data)?,
+                _ => panic!("expected a vote"),
+            };
+            state = calculate(&source.contract, &source.app, &pk, state, input)?;
+        }
+
+        let tally = decrypt(&source.contract, &source.app, &state, &sk)?;
+        assert_eq!(
+            tally.iter().map(|t| t.to_string()).collect::<Vec<_>>(),
+            vec!["0", "0", "2"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_fetches_the_deployers_zk_params() -> Result<(), Box<dyn std::error::Error>> {
+        let store = test_store("zk");
+        let squatter = local_store(&store.root, OTHER_ETH_KEY);
+
+        let (_, contract_id) = store.deploy_contract("{}").await?;
+        let source = store.fetch_source(contract_id.clone()).await?.remove(0);
+        assert!(store.fetch_zk(&source).await.is_err());
+
+        // published first, but not by the deployer
+        squatter.deploy_zksnark(&contract_id, vec![6, 6, 6]).await?;
+        assert!(store.fetch_zk(&source).await.is_err());
+        assert_eq!(store.validity_proof(&contract_id).await, None);
+
+        let (tx_id, _) = store.deploy_zksnark(&contract_id, vec![1, 2, 3]).await?;
+        let zk = store.fetch_zk(&source).await?;
+        assert_eq!(zk.data, vec![1, 2, 3]);
+        assert_eq!(zk.meta.contract, contract_id);
+        assert_eq!(store.validity_proof(&contract_id).await, Some(tx_id));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_signs_everything_but_anonymous_votes() -> Result<(), Box<dyn std::error::Error>> {
+        let store = test_store("publish");
+
+        store
+            .publish(
+                "contract",
+                Input::new(Action::Close),
+                ContractType::Interaction,
+                b"{}".to_vec(),
+            )
+            .await?;
+        store.vote_anonymously("contract", "{}".to_string()).await?;
+
+        let (_, interactions) = store.fetch_latest_state("contract".to_string()).await?;
+        assert_eq!(interactions[0].meta.input.action, Action::Close);
+        interactions[0].meta.verify_eth_signature()?;
+        assert_eq!(interactions[1].meta.input.action, Action::AnonymousVote);
+        assert!(interactions[1].meta.eth_address.is_empty());
+        assert!(interactions[1].meta.eth_signature.is_empty());
+        Ok(())
+    }
+}
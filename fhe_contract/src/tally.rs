@@ -0,0 +1,94 @@
+use crate::arweave::{encode_hex, sha_256};
+use crate::error::HarpocratesError;
+use crate::replay::{DuplicatePolicy, Identity};
+use serde::{Deserialize, Serialize};
+use sunscreen::Ciphertext;
+
+/// Decrypted tally of a poll as published by its deployer, along with what it was decrypted from.
+///
+/// Anyone can replay the interactions up to `last_interaction` with the same policy and get the
+/// same state, so the result is bound to the published ballots and key. Nothing shows the state
+/// decrypts to `tally`: only the deployer, who signed it, knows. Nor does the tally have to add
+/// up to the ballots, a proof doesn't bind what the voter encrypted (see the README).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PublishedResult {
+    /// Decrypted value of every column, as shown by `compute-latest`
+    pub tally: Vec<String>,
+    /// Hex sha256 of the final state, as serialized in the transactions
+    pub state_sha256: String,
+    /// Hex sha256 of the published public key the ballots are encrypted to
+    pub public_key_sha256: String,
+    /// The state accounts for every interaction up to this one, included
+    pub last_interaction: String,
+    /// Number of ballots in the state
+    pub ballots: usize,
+    pub duplicates: DuplicatePolicy,
+    pub identity: Identity,
+}
+
+/// Hex sha256 of a state, as `state_sha256`
+pub fn state_hash(state: &[Ciphertext]) -> Result<String, HarpocratesError> {
+    Ok(encode_hex(&sha_256(
+        serde_json::to_string(state)?.as_bytes(),
+    )))
+}
+
+impl PublishedResult {
+    /// Checks the result is consistent with the published key, and the state and number of
+    /// ballots replayed from the interactions. It is not a verification of the decryption
+    pub fn check(
+        &self,
+        public_key: &str,
+        state_sha256: &str,
+        ballots: usize,
+    ) -> Result<(), HarpocratesError> {
+        let invalid = |reason: String| Err(HarpocratesError::Contract(reason));
+
+        if encode_hex(&sha_256(public_key.as_bytes())) != self.public_key_sha256 {
+            return invalid("the result was decrypted with another key".to_string());
+        }
+        if state_sha256 != self.state_sha256 {
+            return invalid(format!(
+                "the result was decrypted from state {}, the interactions give {}",
+                self.state_sha256, state_sha256
+            ));
+        }
+        if ballots != self.ballots {
+            return invalid(format!(
+                "the result counts {} ballots, the interactions have {}",
+                self.ballots, ballots
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_checks_a_result_against_the_replay() -> Result<(), HarpocratesError> {
+        let state = state_hash(&[])?;
+        let result = PublishedResult {
+            tally: vec!["2".to_string(), "0".to_string(), "1".to_string()],
+            state_sha256: state.clone(),
+            public_key_sha256: encode_hex(&sha_256(b"pk")),
+            last_interaction: "tx".to_string(),
+            ballots: 3,
+            duplicates: DuplicatePolicy::First,
+            identity: Identity::EthAddress,
+        };
+        result.check("pk", &state, 3)?;
+
+        assert!(result.check("another pk", &state, 3).is_err());
+        assert!(result.check("pk", "another state", 3).is_err());
+        assert!(result.check("pk", &state, 4).is_err());
+
+        // a ballot encrypting more than a vote can't keep the result from being published
+        let mut stuffed = result.clone();
+        stuffed.tally[0] = "101".to_string();
+        stuffed.check("pk", &state, 3)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+//! Fixtures shared by the tests of every module
+use crate::arweave::get_unix_timestamp;
+use crate::ethereum::EthSigner;
+use crate::groth16::{check_verification_key, VOTE_PUBLIC_INPUTS};
+use crate::store::LocalStore;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// first hardhat dev account, never holds anything
+pub const TEST_ETH_KEY: &str = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+// second hardhat dev account
+pub const OTHER_ETH_KEY: &str = "59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d";
+
+pub fn signer(key: &str) -> EthSigner {
+    EthSigner::from_private_key(key).expect("a valid test key")
+}
+
+/// A directory of its own for the test `name`, empty and not created yet
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("harpocrates-{}-{}", name, get_unix_timestamp()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+/// A local store in `root` signing with `key`, several of them can share a root
+pub fn local_store(root: &Path, key: &str) -> LocalStore {
+    LocalStore::new(root.to_path_buf(), signer(key)).expect("a writable temp dir")
+}
+
+/// Whether `circom/` holds the keys of the vote circuit as it is now. They are only
+/// regenerated by `make`, so tests proving or checking votes skip until it has been run.
+pub fn vote_circuit_is_built() -> bool {
+    let built = fs::read_to_string("./circom/verification_key.json")
+        .map(|vk| check_verification_key(&vk, VOTE_PUBLIC_INPUTS).is_ok())
+        .unwrap_or(false);
+    if !built {
+        eprintln!("skipped: circom/ holds the keys of an older vote circuit, run `make` first");
+    }
+    built
+}
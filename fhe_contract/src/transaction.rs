@@ -0,0 +1,453 @@
+use crate::action::{Action, Input};
+use crate::allowlist::{AllowlistUpdate, MerkleProof};
+use crate::arweave::{decode_hex, encode_hex, eth_message_for_hash, sha_256};
+use crate::contract::ContractSource;
+use crate::error::HarpocratesError;
+use crate::ethereum::verify_sig;
+use crate::tally::PublishedResult;
+use arloader::transaction::Base64;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use sunscreen::{Ciphertext, PublicKey};
+
+/// Block a transaction was mined in
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Block {
+    pub height: u64,
+    pub timestamp: u64,
+}
+
+/// Where a transaction comes from and its parsed tags, everything but its data
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TxMeta {
+    pub id: String,
+    /// Position in the query results, fetching resumes right after it
+    pub cursor: String,
+    /// Arweave address that paid for the transaction
+    pub owner: String,
+    /// `None` while the transaction is pending
+    pub block: Option<Block>,
+    pub contract: String,
+    /// The `Input` tag, says what the transaction does
+    pub input: Input,
+    /// `Unix-Time` tag, chosen by whoever published the transaction
+    pub unix_time: u64,
+    pub eth_address: String,
+    pub eth_signature: String,
+    /// Hex sha256 of the published data, which is what `eth_signature` signs
+    pub data_sha256: String,
+}
+
+#[derive(Deserialize)]
+struct TxTag {
+    name: String,
+    value: String,
+}
+
+impl TxMeta {
+    /// Checks that `eth_signature` was made by `eth_address` over the published data
+    pub fn verify_eth_signature(&self) -> Result<(), HarpocratesError> {
+        let missing = |what: &str| HarpocratesError::Signature(format!("missing {}", what));
+        if self.eth_address.is_empty() {
+            return Err(missing("Eth-Address tag"));
+        }
+        if self.eth_signature.is_empty() {
+            return Err(missing("Eth-Signature tag"));
+        }
+
+        let message = eth_message_for_hash(&self.eth_address, &decode_hex(&self.data_sha256)?);
+        verify_sig(&message, &self.eth_signature, &self.eth_address)
+    }
+}
+
+/// A fetched transaction whose data hasn't been interpreted yet
+pub struct RawTx {
+    pub meta: TxMeta,
+    pub data: Vec<u8>,
+}
+
+impl RawTx {
+    /// Parses a record shaped like `{"cursor", "id", "owner", "block", "tags"}`,
+    /// `data` being the bytes published with it
+    pub fn from_record(record: &Value, data: Vec<u8>) -> Result<Self, HarpocratesError> {
+        let field = |name: &str| -> Result<String, HarpocratesError> {
+            record[name].as_str().map(String::from).ok_or_else(|| {
+                HarpocratesError::Serialization(format!("record without {}: {}", name, record))
+            })
+        };
+        let id = field("id")?;
+
+        let tags: Vec<TxTag> = serde_json::from_value(record["tags"].clone())?;
+        let tag = |name: &str| {
+            tags.iter()
+                .find(|tag| tag.name == name)
+                .map(|tag| tag.value.clone())
+        };
+        let required = |name: &str| {
+            tag(name).ok_or_else(|| {
+                HarpocratesError::Serialization(format!("transaction {} has no {} tag", id, name))
+            })
+        };
+
+        let input = Input::parse(&required("Input")?);
+        let unix_time = required("Unix-Time")?;
+        let meta = TxMeta {
+            cursor: field("cursor")?,
+            owner: field("owner")?,
+            block: serde_json::from_value(record["block"].clone())?,
+            contract: required("Contract")?,
+            unix_time: unix_time.parse().map_err(|_| {
+                HarpocratesError::Serialization(format!("invalid Unix-Time {}", unix_time))
+            })?,
+            input,
+            eth_address: tag("Eth-Address").unwrap_or_default(),
+            eth_signature: tag("Eth-Signature").unwrap_or_default(),
+            data_sha256: encode_hex(&sha_256(&data)),
+            id,
+        };
+
+        Ok(RawTx { meta, data })
+    }
+}
+
+/// Transaction deploying a contract
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SourceTx {
+    pub meta: TxMeta,
+    /// The published `ContractSource`, kept as is: its `Application` can't be read back from a `Value`
+    pub source: String,
+}
+
+impl SourceTx {
+    pub fn contract<C: DeserializeOwned>(&self) -> Result<ContractSource<C>, HarpocratesError> {
+        Ok(serde_json::from_str(&self.source)?)
+    }
+
+    /// Checks the source was signed by its deployer and reproduces the ID it was published under,
+    /// anyone can tag a transaction with the ID of a contract
+    pub fn verify(&self) -> Result<(), HarpocratesError> {
+        self.meta.verify_eth_signature()?;
+        let expected = contract_id(
+            &self.meta.eth_address,
+            &self.meta.eth_signature,
+            self.source.as_bytes(),
+        );
+        if self.meta.contract != expected {
+            return Err(HarpocratesError::Contract(format!(
+                "source {} is published under contract {} but derives {}",
+                self.meta.id, self.meta.contract, expected
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// ID of a contract, derived from the source transaction: who deployed it, their signature
+/// over the source and the hash of the source, which holds the program and a nonce.
+///
+/// Only the deployer can sign a source reproducing it, so an ID can't be squatted.
+pub fn contract_id(eth_address: &str, eth_signature: &str, source: &[u8]) -> String {
+    let id_data = format!(
+        "harpocrates-contract:{}:{}:{}",
+        eth_address.to_lowercase(),
+        eth_signature.to_lowercase(),
+        encode_hex(&sha_256(source))
+    );
+    Base64(sha_256(id_data.as_bytes()).to_vec()).to_string()
+}
+
+impl TryFrom<RawTx> for SourceTx {
+    type Error = HarpocratesError;
+
+    fn try_from(tx: RawTx) -> Result<Self, Self::Error> {
+        let source = String::from_utf8(tx.data).map_err(|_| {
+            HarpocratesError::Serialization(format!("source {} is not utf8", tx.meta.id))
+        })?;
+        Ok(SourceTx {
+            meta: tx.meta,
+            source,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ZKProof {
+    pub proof: String,
+    pub public: String,
+}
+
+/// Data of a vote: the encrypted ballot and the proof it is valid
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VoteData {
+    pub data: String,
+    pub zkp: ZKProof,
+    /// Proof the voter is on the allowlist, when the contract only published its Merkle root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub membership: Option<MerkleProof>,
+}
+
+/// One of the ballots summed in an aggregate, everything but its ciphertext
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AggregatedBallot {
+    pub eth_address: String,
+    /// `groth16::BallotBinding` of the ciphertext the proof is about
+    pub binding: String,
+    pub zkp: ZKProof,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub membership: Option<MerkleProof>,
+    /// Signature of the voter over `AggregateVote::message`
+    pub eth_signature: String,
+}
+
+/// Data of an aggregated vote: the sum of the ballots of several voters, so none of them
+/// can be decrypted on its own
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AggregateVote {
+    pub contract: String,
+    /// Sum of the encrypted ballots, serialized as JSON
+    pub data: String,
+    pub ballots: Vec<AggregatedBallot>,
+}
+
+impl AggregateVote {
+    /// What every voter of the aggregate signs: the sum and whose ballots are in it
+    pub fn message(&self) -> Vec<u8> {
+        let ballots: Vec<_> = self
+            .ballots
+            .iter()
+            .map(|ballot| [ballot.binding.as_str(), ballot.eth_address.as_str()])
+            .collect();
+        serde_json::json!({
+            "contract": self.contract,
+            "data": self.data,
+            "ballots": ballots,
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    /// Checks that every voter signed the aggregate
+    pub fn verify_signatures(&self) -> Result<(), HarpocratesError> {
+        let hash = sha_256(&self.message());
+        for ballot in &self.ballots {
+            let message = eth_message_for_hash(&ballot.eth_address, &hash);
+            verify_sig(&message, &ballot.eth_signature, &ballot.eth_address).map_err(|e| {
+                HarpocratesError::Signature(format!(
+                    "{} did not sign the aggregate: {}",
+                    ballot.eth_address, e
+                ))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// What an interaction does to the contract state
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Interaction {
+    /// The encrypted state the contract starts from
+    InitState(Vec<Ciphertext>),
+    Vote(VoteData),
+    /// The sum of several votes, published by one of their voters
+    AggregateVote(AggregateVote),
+    /// A vote without identity tags, the voter proves they are on the allowlist instead
+    AnonymousVote(VoteData),
+    /// Members added to or removed from the allowlist, only the deployer can publish it
+    Allowlist(AllowlistUpdate),
+    /// End of the poll, only the deployer can publish it and nothing counts after it
+    Close,
+    /// Decrypted tally, only the deployer can publish it
+    Decrypt(PublishedResult),
+    /// Anyone can publish to a contract, what can't be understood is kept with the reason
+    Invalid(String),
+}
+
+/// Transaction interacting with a contract
+#[derive(Serialize, Deserialize, Clone)]
+pub struct InteractionTx {
+    pub meta: TxMeta,
+    pub interaction: Interaction,
+}
+
+impl From<RawTx> for InteractionTx {
+    fn from(tx: RawTx) -> Self {
+        let action = tx.meta.input.action;
+        let interaction = match action {
+            Action::InitState => serde_json::from_slice(&tx.data).map(Interaction::InitState),
+            Action::Vote => serde_json::from_slice(&tx.data).map(Interaction::Vote),
+            Action::AggregateVote => {
+                serde_json::from_slice(&tx.data).map(Interaction::AggregateVote)
+            }
+            Action::AnonymousVote => {
+                serde_json::from_slice(&tx.data).map(Interaction::AnonymousVote)
+            }
+            Action::Allowlist => serde_json::from_slice(&tx.data).map(Interaction::Allowlist),
+            Action::Close => Ok(Interaction::Close),
+            Action::Decrypt => serde_json::from_slice(&tx.data).map(Interaction::Decrypt),
+            // published with the contract, not to it
+            Action::Deploy | Action::ZkSnark | Action::PublicKey | Action::Unknown => Ok(
+                Interaction::Invalid(format!("{} is not an interaction", action)),
+            ),
+        }
+        .unwrap_or_else(|e| Interaction::Invalid(format!("invalid {}: {}", action, e)));
+
+        InteractionTx {
+            meta: tx.meta,
+            interaction,
+        }
+    }
+}
+
+/// Transaction publishing the zk params of a contract
+pub struct ZkParamsTx {
+    pub meta: TxMeta,
+    /// bincode serialized params
+    pub data: Vec<u8>,
+}
+
+impl From<RawTx> for ZkParamsTx {
+    fn from(tx: RawTx) -> Self {
+        ZkParamsTx {
+            meta: tx.meta,
+            data: tx.data,
+        }
+    }
+}
+
+/// Transaction publishing the FHE public key of the contract admin
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PublicKeyTx {
+    pub meta: TxMeta,
+    /// The key as published, serialized as JSON
+    pub public_key: String,
+}
+
+impl PublicKeyTx {
+    pub fn key(&self) -> Result<PublicKey, HarpocratesError> {
+        serde_json::from_str(&self.public_key).map_err(|e| {
+            HarpocratesError::Key(format!("invalid public key {}: {}", self.meta.id, e))
+        })
+    }
+}
+
+impl TryFrom<RawTx> for PublicKeyTx {
+    type Error = HarpocratesError;
+
+    fn try_from(tx: RawTx) -> Result<Self, Self::Error> {
+        let public_key = String::from_utf8(tx.data).map_err(|_| {
+            HarpocratesError::Serialization(format!("public key {} is not utf8", tx.meta.id))
+        })?;
+        Ok(PublicKeyTx {
+            meta: tx.meta,
+            public_key,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arweave::get_eth_metadata;
+    use crate::testing::{signer, OTHER_ETH_KEY, TEST_ETH_KEY};
+    use serde_json::json;
+
+    fn record(input: &str) -> Value {
+        json!({
+            "id": "tx",
+            "cursor": "0",
+            "owner": "local",
+            "block": {"height": 3, "timestamp": 1660000000},
+            "tags": [
+                {"name": "Contract", "value": "contract"},
+                {"name": "Unix-Time", "value": "1650000000"},
+                {"name": "Input", "value": input},
+                {"name": "Eth-Address", "value": ""},
+            ],
+        })
+    }
+
+    #[test]
+    fn it_parses_tags() -> Result<(), HarpocratesError> {
+        let tx = RawTx::from_record(&record(r#"{"action":"vote","arguments":[]}"#), vec![])?;
+
+        assert_eq!(tx.meta.input.action, Action::Vote);
+        assert_eq!(tx.meta.contract, "contract");
+        assert_eq!(tx.meta.unix_time, 1650000000);
+        assert_eq!(
+            tx.meta.block,
+            Some(Block {
+                height: 3,
+                timestamp: 1660000000
+            })
+        );
+        assert!(matches!(
+            tx.meta.verify_eth_signature(),
+            Err(HarpocratesError::Signature(_))
+        ));
+
+        let mut untagged = record("");
+        untagged["tags"] = json!([]);
+        assert!(RawTx::from_record(&untagged, vec![]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_interactions_it_cannot_read() -> Result<(), HarpocratesError> {
+        let vote = RawTx::from_record(
+            &record(r#"{"action":"vote","arguments":[]}"#),
+            b"not a vote".to_vec(),
+        )?;
+        assert!(matches!(
+            InteractionTx::from(vote).interaction,
+            Interaction::Invalid(_)
+        ));
+
+        let unknown = RawTx::from_record(&record(r#"{"action":"other"}"#), b"[]".to_vec())?;
+        assert!(matches!(
+            InteractionTx::from(unknown).interaction,
+            Interaction::Invalid(_)
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_checks_every_signature_of_an_aggregate() -> Result<(), HarpocratesError> {
+        let signers = [TEST_ETH_KEY, OTHER_ETH_KEY].map(signer);
+
+        let mut ballots = vec![];
+        for (i, signer) in signers.iter().enumerate() {
+            ballots.push(AggregatedBallot {
+                eth_address: signer.eth_address().await?,
+                binding: i.to_string(),
+                zkp: ZKProof {
+                    proof: String::new(),
+                    public: String::new(),
+                },
+                membership: None,
+                eth_signature: String::new(),
+            });
+        }
+        let mut aggregate = AggregateVote {
+            contract: "contract".to_string(),
+            data: "[]".to_string(),
+            ballots,
+        };
+
+        let message = aggregate.message();
+        let (_, first) = get_eth_metadata(&signers[0], &message).await?;
+        aggregate.ballots[0].eth_signature = first.clone();
+        // the second voter didn't sign
+        aggregate.ballots[1].eth_signature = first;
+        assert!(aggregate.verify_signatures().is_err());
+
+        let (_, second) = get_eth_metadata(&signers[1], &message).await?;
+        aggregate.ballots[1].eth_signature = second;
+        aggregate.verify_signatures()?;
+
+        // nor can the sum be changed afterwards
+        aggregate.data = "[1]".to_string();
+        assert!(aggregate.verify_signatures().is_err());
+        Ok(())
+    }
+}